@@ -0,0 +1,219 @@
+use std::fmt;
+
+use crate::lexer::Span;
+use crate::operations::Op;
+use crate::value::Value;
+
+/// Where an evaluation error happened: the op being run, where it came from
+/// in the source, and the operands it was given.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Context {
+    pub op: Option<Op>,
+    pub span: Option<Span>,
+    pub arity: usize,
+    pub stack: Vec<Value>, // top `arity` stack items, bottom first
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    StackUnderflow {
+        found: usize,
+        context: Box<Context>,
+    },
+    LengthMismatch {
+        left: usize,
+        right: usize,
+        context: Box<Context>,
+    },
+    EmptyOperand {
+        context: Box<Context>,
+    },
+    IndexOutOfBounds {
+        index: i32,
+        len: usize,
+        context: Box<Context>,
+    },
+    Overflow {
+        context: Box<Context>,
+    },
+    DomainError {
+        reason: String,
+        index: Option<usize>, // offending element, for elementwise ops
+        context: Box<Context>,
+    },
+    RecursionLimit {
+        limit: usize,
+        context: Box<Context>,
+    },
+    IterationLimit {
+        limit: usize,
+        context: Box<Context>,
+    },
+    NestingLimit {
+        limit: usize,
+        context: Box<Context>,
+    },
+}
+
+impl EvalError {
+    pub fn stack_underflow(found: usize) -> Self {
+        EvalError::StackUnderflow {
+            found,
+            context: Box::default(),
+        }
+    }
+
+    pub fn length_mismatch(left: usize, right: usize) -> Self {
+        EvalError::LengthMismatch {
+            left,
+            right,
+            context: Box::default(),
+        }
+    }
+
+    pub fn empty_operand() -> Self {
+        EvalError::EmptyOperand {
+            context: Box::default(),
+        }
+    }
+
+    pub fn index_out_of_bounds(index: i32, len: usize) -> Self {
+        EvalError::IndexOutOfBounds {
+            index,
+            len,
+            context: Box::default(),
+        }
+    }
+
+    pub fn overflow() -> Self {
+        EvalError::Overflow {
+            context: Box::default(),
+        }
+    }
+
+    pub fn domain_error(reason: impl Into<String>) -> Self {
+        EvalError::DomainError {
+            reason: reason.into(),
+            index: None,
+            context: Box::default(),
+        }
+    }
+
+    pub fn recursion_limit(limit: usize) -> Self {
+        EvalError::RecursionLimit {
+            limit,
+            context: Box::default(),
+        }
+    }
+
+    pub fn iteration_limit(limit: usize) -> Self {
+        EvalError::IterationLimit {
+            limit,
+            context: Box::default(),
+        }
+    }
+
+    pub fn nesting_limit(limit: usize) -> Self {
+        EvalError::NestingLimit {
+            limit,
+            context: Box::default(),
+        }
+    }
+
+    /// Records which element an elementwise domain error happened at.
+    pub fn at_index(mut self, i: usize) -> Self {
+        if let EvalError::DomainError { index, .. } = &mut self {
+            index.get_or_insert(i);
+        }
+        self
+    }
+
+    pub fn context(&self) -> &Context {
+        match self {
+            EvalError::StackUnderflow { context, .. }
+            | EvalError::LengthMismatch { context, .. }
+            | EvalError::EmptyOperand { context }
+            | EvalError::IndexOutOfBounds { context, .. }
+            | EvalError::Overflow { context }
+            | EvalError::DomainError { context, .. }
+            | EvalError::RecursionLimit { context, .. }
+            | EvalError::IterationLimit { context, .. }
+            | EvalError::NestingLimit { context, .. } => context,
+        }
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context {
+        match self {
+            EvalError::StackUnderflow { context, .. }
+            | EvalError::LengthMismatch { context, .. }
+            | EvalError::EmptyOperand { context }
+            | EvalError::IndexOutOfBounds { context, .. }
+            | EvalError::Overflow { context }
+            | EvalError::DomainError { context, .. }
+            | EvalError::RecursionLimit { context, .. }
+            | EvalError::IterationLimit { context, .. }
+            | EvalError::NestingLimit { context, .. } => context,
+        }
+    }
+
+    /// Fills in which op failed, unless an inner op already claimed the error.
+    pub fn with_context(mut self, context: Context) -> Self {
+        if self.context().op.is_none() {
+            *self.context_mut() = context;
+        }
+        self
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = self.context();
+        if let Some(span) = context.span {
+            write!(f, "{}:{}: ", span.line, span.column)?;
+        }
+        let op = context
+            .op
+            .as_ref()
+            .map_or_else(|| "op".to_string(), |op| format!("'{op}'"));
+
+        match self {
+            EvalError::StackUnderflow { found, .. } => write!(
+                f,
+                "{op} needs {} value(s) on the stack, found {found}",
+                context.arity
+            )?,
+            EvalError::LengthMismatch { left, right, .. } => write!(
+                f,
+                "{op} needs arrays of the same length, got {left} and {right}"
+            )?,
+            EvalError::EmptyOperand { .. } => write!(f, "{op} was given an empty array")?,
+            EvalError::IndexOutOfBounds { index, len, .. } => {
+                write!(f, "{op} index {index} is out of bounds for length {len}")?
+            }
+            EvalError::Overflow { .. } => write!(f, "{op} overflowed")?,
+            EvalError::DomainError {
+                reason,
+                index: Some(i),
+                ..
+            } => write!(f, "{op} {reason} (at element {i})")?,
+            EvalError::DomainError { reason, .. } => write!(f, "{op} {reason}")?,
+            EvalError::RecursionLimit { limit, .. } => {
+                write!(f, "{op} went more than {limit} quotations deep")?
+            }
+            EvalError::IterationLimit { limit, .. } => {
+                write!(f, "{op} ran more than {limit} iterations")?
+            }
+            EvalError::NestingLimit { limit, .. } => {
+                write!(f, "{op} would nest boxes more than {limit} deep")?
+            }
+        }
+
+        if !context.stack.is_empty() {
+            let operands: Vec<String> = context.stack.iter().map(Value::to_string).collect();
+            write!(f, " (operands: {})", operands.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for EvalError {}
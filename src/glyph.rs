@@ -1,480 +1,2111 @@
-use crate::operations::Op;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
+use std::mem;
+use std::str::FromStr;
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{Signed, Zero};
+
+use crate::error::{Context, EvalError};
+use crate::number::{DivisionPolicy, Number, OverflowPolicy};
+use crate::operations::{Instruction, Op, Program, Quotation};
+use crate::parser::{self, ParseError};
+use crate::value::{Element, Value};
+
+/// How deeply quotations may run inside each other before evaluation gives
+/// up, so that runaway recursion is an error rather than a crash. A debug
+/// build uses up to about 4 KiB of stack per level, so this fits well within
+/// the 2 MiB that spawned threads get by default.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// How many times a single loop may run before evaluation gives up.
+pub const DEFAULT_MAX_ITERATIONS: usize = 100_000;
+
+/// The index of the first row, as in APL's `⎕IO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexOrigin {
+    #[default]
+    Zero,
+    One,
+}
+
+impl IndexOrigin {
+    fn offset(self) -> usize {
+        match self {
+            IndexOrigin::Zero => 0,
+            IndexOrigin::One => 1,
+        }
+    }
+}
+
+impl FromStr for IndexOrigin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(IndexOrigin::Zero),
+            "1" => Ok(IndexOrigin::One),
+            _ => Err(format!("unknown index origin '{s}' (expected 0 or 1)")),
+        }
+    }
+}
 
 pub struct Glyph {
-    pub stack: Vec<Vec<i32>>,
+    pub stack: Vec<Value>,
+    pub overflow: OverflowPolicy,
+    pub division: DivisionPolicy,
+    pub max_depth: usize,
+    pub max_iterations: usize,
+    pub index_origin: IndexOrigin, // used by every glyph that deals in indices
+    pub words: BTreeMap<String, Quotation>, // user-defined words by name
+    pub variables: BTreeMap<String, Value>, // bindings made outside any word
+    locals: Vec<BTreeMap<String, Value>>, // bindings of each running word
+    depth: usize,                  // quotations currently running
+}
+
+impl Default for Glyph {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Glyph {
     pub fn new() -> Self {
-        Glyph { stack: Vec::new() }
+        Glyph {
+            stack: Vec::new(),
+            overflow: OverflowPolicy::default(),
+            division: DivisionPolicy::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            index_origin: IndexOrigin::default(),
+            words: BTreeMap::new(),
+            variables: BTreeMap::new(),
+            locals: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn with_division(mut self, division: DivisionPolicy) -> Self {
+        self.division = division;
+        self
+    }
+
+    pub fn with_index_origin(mut self, index_origin: IndexOrigin) -> Self {
+        self.index_origin = index_origin;
+        self
+    }
+
+    /// Evaluates a single instruction. If it fails, the stack is restored to
+    /// exactly what it was before the instruction ran.
+    pub fn evaluate(&mut self, instruction: Instruction) -> Result<(), EvalError> {
+        self.step(&instruction)
+    }
+
+    fn step(&mut self, instruction: &Instruction) -> Result<(), EvalError> {
+        let Instruction { op, span } = instruction;
+        let arity = op.arity();
+        let base = self.stack.len().saturating_sub(arity);
+        // Quotations may consume more than the op's operands, so keep
+        // everything. Inside a quotation there's no need: whatever started
+        // running it restores the whole stack anyway
+        let saved = if op.runs_code() && self.depth == 0 {
+            0
+        } else {
+            base
+        };
+        let snapshot = self.stack[saved..].to_vec();
+
+        self.control(op).map_err(|mut e| {
+            self.stack.truncate(saved);
+            self.stack.extend(snapshot.iter().cloned());
+            if op.runs_code() {
+                // The quotation may have been written in earlier input, whose
+                // spans mean nothing here, so point at where it was run instead
+                e.context_mut().span = Some(*span);
+            }
+            e.with_context(Context {
+                op: Some(op.clone()),
+                span: Some(*span),
+                arity,
+                stack: snapshot[base - saved..].to_vec(),
+            })
+        })
+    }
+
+    /// Runs the body of a quotation on the stack.
+    fn run(&mut self, quotation: &Quotation) -> Result<(), EvalError> {
+        if self.depth >= self.max_depth {
+            return Err(EvalError::recursion_limit(self.max_depth));
+        }
+        self.depth += 1;
+        let result = quotation
+            .instructions()
+            .iter()
+            .try_for_each(|instruction| self.step(instruction));
+        self.depth -= 1;
+        result
+    }
+
+    /// Applies an op, handing the ones that run code to methods of their own.
+    /// Every level of recursion goes through here, so keeping this frame small
+    /// (rather than the frame of `apply`, which holds every other glyph) is
+    /// what lets quotations nest `DEFAULT_MAX_DEPTH` deep without running out
+    /// of stack, even in a debug build.
+    fn control(&mut self, op: &Op) -> Result<(), EvalError> {
+        if self.stack.len() < op.arity() {
+            return Err(EvalError::stack_underflow(self.stack.len()));
+        }
+
+        match op {
+            Op::Name(name) => self.word(name),
+            Op::Apply => {
+                let value = self.pop()?;
+                let quotation = Self::quotation_of(&value)?;
+                self.run(&quotation)
+            }
+            Op::Each => self.each(),
+            Op::Reduce => self.fold(),
+            Op::Scan => self.scan(),
+            Op::If => self.branch(),
+            Op::Switch => self.switch(),
+            Op::Repeat => self.repeat(),
+            Op::DoWhile => self.do_while(),
+            Op::Converge => self.converge(),
+            Op::Dip => self.dip(),
+            _ => self.apply(op),
+        }
+    }
+
+    /// Pushes the binding called `name`, or runs the word of that name with
+    /// a fresh set of locals.
+    fn word(&mut self, name: &str) -> Result<(), EvalError> {
+        if let Some(value) = self.binding(name) {
+            self.stack.push(value.clone());
+            return Ok(());
+        }
+        let body = self
+            .words
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EvalError::domain_error("is not defined"))?;
+        self.locals.push(BTreeMap::new());
+        let result = self.run(&body);
+        self.locals.pop();
+        result
+    }
+
+    /// Runs a quotation on every row of an array.
+    fn each(&mut self) -> Result<(), EvalError> {
+        let (array, function) = self.pop_pair()?;
+        let quotation = Self::quotation_of(&function)?;
+        let rows = (0..array.len())
+            .map(|i| self.call(&quotation, vec![array.row(i)]))
+            .collect::<Result<_, _>>()?;
+        self.stack.push(Value::from_rows(rows, self.max_depth)?);
+        Ok(())
+    }
+
+    /// Folds the rows of an array together with a quotation.
+    fn fold(&mut self) -> Result<(), EvalError> {
+        let (array, function) = self.pop_pair()?;
+        let quotation = Self::quotation_of(&function)?;
+        if array.is_empty() {
+            return Err(EvalError::empty_operand());
+        }
+        let mut acc = array.row(0);
+        for i in 1..array.len() {
+            acc = self.call(&quotation, vec![acc, array.row(i)])?;
+        }
+        self.stack.push(acc);
+        Ok(())
+    }
+
+    /// Like `fold`, but keeps every running result.
+    fn scan(&mut self) -> Result<(), EvalError> {
+        let (array, function) = self.pop_pair()?;
+        let quotation = Self::quotation_of(&function)?;
+        let mut rows: Vec<Value> = Vec::with_capacity(array.len());
+        for i in 0..array.len() {
+            let row = match rows.last() {
+                Some(acc) => self.call(&quotation, vec![acc.clone(), array.row(i)])?,
+                None => array.row(i),
+            };
+            rows.push(row);
+        }
+        self.stack.push(Value::from_rows(rows, self.max_depth)?);
+        Ok(())
+    }
+
+    /// Runs one of two quotations, depending on a condition.
+    fn branch(&mut self) -> Result<(), EvalError> {
+        let (then, otherwise) = self.pop_pair()?;
+        let condition = self.pop()?;
+        let branch = if condition.truthy()? { then } else { otherwise };
+        self.run(&Self::quotation_of(&branch)?)
+    }
+
+    /// Runs the quotation at an index in a list of quotations.
+    fn switch(&mut self) -> Result<(), EvalError> {
+        let (index, branches) = self.pop_pair()?;
+        let index = index.data.first().ok_or_else(EvalError::empty_operand)?;
+        let i = self.index(index, branches.data.len())?;
+        match &branches.data[i] {
+            Element::Quotation(quotation) => self.run(&quotation.clone()),
+            _ => Err(EvalError::domain_error("expected a quotation")),
+        }
+    }
+
+    /// Runs a quotation a given number of times.
+    fn repeat(&mut self) -> Result<(), EvalError> {
+        let (count, function) = self.pop_pair()?;
+        let quotation = Self::quotation_of(&function)?;
+        let n = count
+            .first_number()?
+            .whole_usize("needs a non-negative whole number")?;
+        if n > self.max_iterations {
+            return Err(EvalError::iteration_limit(self.max_iterations));
+        }
+        for _ in 0..n {
+            self.run(&quotation)?;
+        }
+        Ok(())
+    }
+
+    /// Runs a quotation for as long as it leaves a true condition.
+    fn do_while(&mut self) -> Result<(), EvalError> {
+        let function = self.pop()?;
+        let quotation = Self::quotation_of(&function)?;
+        for _ in 0..self.max_iterations {
+            self.run(&quotation)?;
+            let condition = self.stack.pop().ok_or_else(|| {
+                EvalError::domain_error("needs a quotation that leaves a condition")
+            })?;
+            if !condition.truthy()? {
+                return Ok(());
+            }
+        }
+        Err(EvalError::iteration_limit(self.max_iterations))
+    }
+
+    /// Runs a quotation on a value until the result stops changing.
+    fn converge(&mut self) -> Result<(), EvalError> {
+        let (mut value, function) = self.pop_pair()?;
+        let quotation = Self::quotation_of(&function)?;
+        for _ in 0..self.max_iterations {
+            let next = self.call(&quotation, vec![value.clone()])?;
+            if next == value {
+                self.stack.push(next);
+                return Ok(());
+            }
+            value = next;
+        }
+        Err(EvalError::iteration_limit(self.max_iterations))
+    }
+
+    /// Runs a quotation under the top item of the stack.
+    fn dip(&mut self) -> Result<(), EvalError> {
+        let (top, function) = self.pop_pair()?;
+        self.run(&Self::quotation_of(&function)?)?;
+        self.stack.push(top);
+        Ok(())
     }
 
-    pub fn evaluate(&mut self, op: Op) -> Result<(), String> {
+    fn apply(&mut self, op: &Op) -> Result<(), EvalError> {
+        let policy = self.overflow;
+        let division = self.division;
+
         match op {
             Op::Number(n) => {
-                self.stack.push(vec![n]);
-                Ok(())
+                self.stack.push(Value::scalar(n.clone()));
             }
-            Op::Range => {
-                if let Some(vec) = self.stack.pop() {
-                    if let Some(n) = vec.first() {
-                        self.stack.push((1..=*n).collect());
-                        Ok(())
-                    } else {
-                        Err("Must have a number on the stack to create a range".into())
-                    }
-                } else {
-                    Err("Must have a number on the stack to create a range".into())
-                }
+            Op::Array(value) => {
+                self.stack.push(value.clone());
             }
-            Op::Add => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b.iter().map(|x| x + a[0]).collect(),
-                        (_, 1) => a.iter().map(|x| x + b[0]).collect(),
-                        (_, _) => a.iter().zip(b.iter()).map(|(x, y)| x + y).collect(),
-                    });
-                    Ok(())
-                } else {
-                    Err("Must have two numbers on the stack to add".into())
-                }
+            Op::Quotation(quotation) => {
+                self.stack
+                    .push(Value::scalar(Element::Quotation(quotation.clone())));
             }
-            Op::Subtract => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b.iter().map(|x| a[0] - x).collect(),
-                        (_, 1) => a.iter().map(|x| x - b[0]).collect(),
-                        (_, _) => a.iter().zip(b.iter()).map(|(x, y)| x - y).collect(),
-                    });
-                    Ok(())
-                } else {
-                    Err("Must have two numbers on the stack to subtract".into())
-                }
+            Op::Define(name, body) => {
+                self.words.insert(name.clone(), body.clone());
             }
-            Op::Multiply => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack
-                        .push(a.iter().zip(b.iter()).map(|(x, y)| x * y).collect());
-                    Ok(())
-                } else {
-                    Err("Must have two numbers on the stack to multiply".into())
-                }
+            Op::Assign(name) => {
+                let value = self.pop()?;
+                let scope = self.locals.last_mut().unwrap_or(&mut self.variables);
+                scope.insert(name.clone(), value);
             }
-            Op::Divide => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b
-                            .iter()
-                            .map(|x| if *x != 0 { a[0] / x } else { 0 })
-                            .collect(),
-                        (_, 1) => a
-                            .iter()
-                            .map(|x| if b[0] != 0 { x / b[0] } else { 0 })
-                            .collect(),
-                        (_, _) => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| if *y != 0 { x / y } else { 0 })
-                            .collect(),
-                    });
-                    Ok(())
-                } else {
-                    Err("Must have two numbers on the stack to divide".into())
-                }
+            Op::Range => {
+                let value = self.pop()?;
+                let bounds = value
+                    .data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| x.as_number().map_err(|e| e.at_index(i)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let range = match bounds.as_slice() {
+                    [n] => {
+                        let n = n.whole_i32("needs a whole number")?;
+                        // Counting starts from 1 whatever the index origin,
+                        // which only `⍳` follows
+                        let count = Value::size(&[n.unsigned_abs() as usize])?;
+                        let counts = naturals(0..count, 1)?;
+                        if n < 0 {
+                            counts
+                                .data
+                                .iter()
+                                .map(|i| i.as_number()?.neg(policy))
+                                .collect::<Result<_, _>>()?
+                        } else {
+                            counts
+                        }
+                    }
+                    [start, stop] => {
+                        let step = Number::Int(if stop < start { -1 } else { 1 });
+                        range(start, stop, &step)?
+                    }
+                    [start, stop, step] => range(start, stop, step)?,
+                    _ => {
+                        return Err(EvalError::domain_error(
+                            "needs a count, [start stop] or [start stop step]",
+                        ))
+                    }
+                };
+                self.stack.push(range);
             }
-            Op::Modulo => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b
-                            .iter()
-                            .map(|x| if *x != 0 { a[0] % x } else { 0 })
-                            .collect(),
-                        (_, 1) => a
-                            .iter()
-                            .map(|x| if b[0] != 0 { x % b[0] } else { 0 })
-                            .collect(),
-                        (_, _) => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| if *y != 0 { x % y } else { 0 })
-                            .collect(),
-                    });
-                    Ok(())
-                } else {
-                    Err("Must have two numbers on the stack to modulo".into())
+            Op::Indices => {
+                let value = self.pop()?;
+                let shape = Self::shape_of(&value)?;
+                if shape.is_empty() {
+                    return Err(EvalError::domain_error("needs at least one axis"));
                 }
-            }
-            Op::Sum => {
-                if let Some(vec) = self.stack.pop() {
-                    let sum = vec.iter().sum();
-                    self.stack.push(vec![sum]);
-                    Ok(())
-                } else {
-                    Err("Must have an array on the stack to sum".into())
+                let size = Value::size(&shape)?;
+                // Every element is an index with one number per axis
+                Value::size(&[size, shape.len()])?;
+                let mut data = Vec::with_capacity(size * shape.len());
+                for flat in 0..size {
+                    let mut index = Vec::with_capacity(shape.len());
+                    let mut rest = flat;
+                    for len in shape.iter().rev() {
+                        index.push(rest % len);
+                        rest /= len;
+                    }
+                    index.reverse();
+                    data.extend(self.indices(index)?.data);
                 }
+                let shape = match shape.as_slice() {
+                    [_] => shape,
+                    _ => [&shape[..], &[shape.len()]].concat(),
+                };
+                self.stack.push(Value::with_shape(shape, data));
             }
-            Op::Product => {
-                if let Some(vec) = self.stack.pop() {
-                    let product = vec.iter().product();
-                    self.stack.push(vec![product]);
-                    Ok(())
-                } else {
-                    Err("Must have an array on the stack to product".into())
-                }
+            Op::Add => self.dyadic(|x, y| x.add(y, policy))?,
+            Op::Subtract => self.dyadic(|x, y| x.sub(y, policy))?,
+            Op::Multiply => self.dyadic(|x, y| x.mul(y, policy))?,
+            Op::Divide => self.dyadic(|x, y| x.div(y, policy, division))?,
+            Op::Modulo => self.dyadic(|x, y| x.rem(y, policy, division))?,
+            Op::Power => self.dyadic(|x, y| x.pow(y, policy))?,
+            Op::Minimum => {
+                self.dyadic_atoms(|x, y| Ok(if order(x, y)?.is_gt() { y } else { x }.clone()))?
+            }
+            Op::Maximum => {
+                self.dyadic_atoms(|x, y| Ok(if order(x, y)?.is_gt() { x } else { y }.clone()))?
             }
+            Op::Gcd => self.dyadic(|x, y| x.gcd(y, policy))?,
+            Op::Lcm => self.dyadic(|x, y| x.lcm(y, policy))?,
+            Op::Negate => self.monadic(|x| x.neg(policy))?,
+            Op::Abs => self.monadic(|x| x.abs(policy))?,
+            Op::Sign => self.monadic(|x| Ok(x.sign()))?,
+            Op::Floor => self.monadic(|x| x.floor(policy))?,
+            Op::Ceiling => self.monadic(|x| x.ceil(policy))?,
+            Op::Sqrt => self.monadic(|x| x.isqrt(policy))?,
+            Op::Sum => self.reduce(Number::Int(0), |x, y| x.add(y, policy))?,
+            Op::Product => self.reduce(Number::Int(1), |x, y| x.mul(y, policy))?,
             Op::Reverse => {
-                if let Some(mut vec) = self.stack.pop() {
-                    vec.reverse();
-                    self.stack.push(vec);
-                    Ok(())
-                } else {
-                    Err("Must have an array on the stack to reverse".into())
-                }
+                let value = self.pop()?;
+                let mut cells = value.cells();
+                cells.reverse();
+                self.stack.push(value.with_cells(&cells));
             }
             Op::Sort => {
-                if let Some(mut vec) = self.stack.pop() {
-                    vec.sort();
-                    self.stack.push(vec);
-                    Ok(())
-                } else {
-                    Err("Must have an array on the stack to sort".into())
+                let value = self.pop()?;
+                let mut cells = value.cells();
+                cells.sort();
+                self.stack.push(value.with_cells(&cells));
+            }
+            Op::SortDown => {
+                let value = self.pop()?;
+                let mut cells = value.cells();
+                cells.sort_by(|a, b| b.cmp(a));
+                self.stack.push(value.with_cells(&cells));
+            }
+            Op::GradeUp | Op::GradeDown => {
+                let value = self.pop()?;
+                let order = value.grade(matches!(op, Op::GradeDown));
+                self.stack.push(self.indices(order)?);
+            }
+            Op::SortBy => {
+                let (array, keys) = self.pop_pair()?;
+                if array.len() != keys.len() {
+                    return Err(EvalError::length_mismatch(array.len(), keys.len()));
                 }
+                let cells = array.cells();
+                let sorted: Vec<_> = keys.grade(false).into_iter().map(|i| cells[i]).collect();
+                self.stack.push(array.with_cells(&sorted));
             }
             Op::Length => {
-                if let Some(vec) = self.stack.pop() {
-                    self.stack.push(vec![vec.len() as i32]);
-                    Ok(())
-                } else {
-                    Err("Must have an array on the stack to get its length".into())
-                }
+                let value = self.pop()?;
+                let len = i32::try_from(value.len()).map_err(|_| EvalError::overflow())?;
+                self.stack.push(Value::scalar(Number::Int(len)));
             }
-            Op::Greater => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b.iter().map(|x| i32::from(a[0] > *x)).collect(),
-                        (_, 1) => a.iter().map(|x| i32::from(*x > b[0])).collect(),
-                        (_, _) => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| i32::from(x > y))
-                            .collect(),
-                    });
-                    Ok(())
-                } else {
-                    Err("Must have two elements on the stack to compare".into())
-                }
+            Op::Greater => self.compare(Ordering::is_gt)?,
+            Op::GreaterEqual => self.compare(Ordering::is_ge)?,
+            Op::Less => self.compare(Ordering::is_lt)?,
+            Op::LessEqual => self.compare(Ordering::is_le)?,
+            Op::Equal => self.dyadic_atoms(|x, y| Ok(Element::from(x == y)))?,
+            Op::Unique => {
+                let value = self.pop()?;
+                let mut seen = BTreeSet::new();
+                let cells: Vec<_> = value
+                    .cells()
+                    .into_iter()
+                    .filter(|c| seen.insert(*c))
+                    .collect();
+                self.stack.push(value.with_cells(&cells));
             }
-            Op::GreaterEqual => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b.iter().map(|x| i32::from(a[0] >= *x)).collect(),
-                        (_, 1) => a.iter().map(|x| i32::from(*x >= b[0])).collect(),
-                        (_, _) => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| i32::from(x >= y))
-                            .collect(),
-                    });
-                    Ok(())
-                } else {
-                    Err("Must have two elements on the stack to compare".into())
-                }
+            Op::Sieve => {
+                let value = self.pop()?;
+                let mut seen = BTreeSet::new();
+                let sieve = value
+                    .cells()
+                    .into_iter()
+                    .map(|c| Element::from(seen.insert(c)));
+                self.stack.push(sieve.collect());
             }
-            Op::Less => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b.iter().map(|x| i32::from(a[0] < *x)).collect(),
-                        (_, 1) => a.iter().map(|x| i32::from(*x < b[0])).collect(),
-                        (_, _) => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| i32::from(x < y))
-                            .collect(),
-                    });
-                    Ok(())
-                } else {
-                    Err("Must have two elements on the stack to compare".into())
-                }
+            Op::Member => {
+                let (items, set) = self.pop_pair()?;
+                let set: BTreeSet<_> = set.data.iter().collect();
+                let data = items.data.iter().map(|x| Element::from(set.contains(x)));
+                self.stack
+                    .push(Value::with_shape(items.shape.clone(), data.collect()));
             }
-            Op::LessEqual => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b.iter().map(|x| i32::from(a[0] <= *x)).collect(),
-                        (_, 1) => a.iter().map(|x| i32::from(*x <= b[0])).collect(),
-                        (_, _) => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| i32::from(x <= y))
-                            .collect(),
-                    });
-                    Ok(())
-                } else {
-                    Err("Must have two elements on the stack to compare".into())
-                }
+            Op::IndexOf => {
+                let (haystack, needles) = self.pop_pair()?;
+                let (shape, keys) = Self::search_keys(&haystack, &needles)?;
+                let rows = haystack.cells();
+                let indices = keys
+                    .iter()
+                    .map(|key| rows.iter().position(|row| row == key).unwrap_or(rows.len()));
+                let indices = self.indices(indices)?;
+                self.stack.push(Value::with_shape(shape, indices.data));
             }
-            Op::Equal => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(match (a.len(), b.len()) {
-                        (1, _) => b.iter().map(|x| i32::from(a[0] == *x)).collect(),
-                        (_, 1) => a.iter().map(|x| i32::from(*x == b[0])).collect(),
-                        (_, _) => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| i32::from(x == y))
-                            .collect(),
-                    });
-                    Ok(())
+            Op::Occurrences => {
+                let (haystack, needles) = self.pop_pair()?;
+                let (shape, keys) = Self::search_keys(&haystack, &needles)?;
+                let rows = haystack.cells();
+                let counts = keys
+                    .iter()
+                    .map(|key| rows.iter().filter(|row| *row == key).count());
+                let counts = naturals(counts, 0)?;
+                self.stack.push(Value::with_shape(shape, counts.data));
+            }
+            Op::Union | Op::Intersection | Op::Difference => {
+                let (a, b) = self.pop_pair()?;
+                // A single row of the other array counts as a set of one row
+                let a = if b.rank() > 1 && a.shape == b.shape[1..] {
+                    Value::with_shape([&[1], &a.shape[..]].concat(), a.data)
+                } else {
+                    a
+                };
+                let b = if a.rank() > 1 && b.shape == a.shape[1..] {
+                    Value::with_shape([&[1], &b.shape[..]].concat(), b.data)
                 } else {
-                    Err("Must have two elements on the stack to compare".into())
+                    b
+                };
+                if a.shape[1..] != b.shape[1..] {
+                    return Err(EvalError::length_mismatch(a.cell_len(), b.cell_len()));
                 }
+                let (a_cells, b_cells) = (a.cells(), b.cells());
+                let in_b: BTreeSet<_> = b_cells.iter().copied().collect();
+                let mut seen = BTreeSet::new();
+                let cells: Vec<_> = match op {
+                    Op::Union => a_cells.into_iter().chain(b_cells).collect(),
+                    Op::Intersection => a_cells.into_iter().filter(|c| in_b.contains(c)).collect(),
+                    _ => a_cells.into_iter().filter(|c| !in_b.contains(c)).collect(),
+                };
+                let cells: Vec<_> = cells.into_iter().filter(|c| seen.insert(*c)).collect();
+                self.stack.push(a.with_cells(&cells));
             }
             Op::Clear => {
                 self.stack.clear();
-                Ok(())
             }
             Op::Filter => {
-                if let (Some(condition), Some(array)) = (self.stack.pop(), self.stack.pop()) {
-                    self.stack.push(
-                        array
-                            .into_iter()
-                            .zip(condition)
-                            .filter(|(_, cond)| *cond != 0)
-                            .map(|(val, _)| val)
-                            .collect(),
-                    );
-                    Ok(())
-                } else {
-                    Err("Must have an array and a condition array on the stack to filter".into())
+                let (array, condition) = self.pop_pair()?;
+                let cells = array.cells();
+                let mask = &condition.data;
+                let rows = match (cells.len(), mask.len()) {
+                    (_, 1) => cells.len(),
+                    (1, n) => n,
+                    (m, n) if m == n => m,
+                    (m, n) => return Err(EvalError::length_mismatch(m, n)),
+                };
+                let mut kept = Vec::new();
+                for i in 0..rows {
+                    let keep = mask[i.min(mask.len() - 1)]
+                        .as_number()
+                        .map_err(|e| e.at_index(i))?;
+                    if !keep.is_zero() {
+                        kept.push(cells[i.min(cells.len() - 1)]);
+                    }
                 }
+                self.stack.push(array.with_cells(&kept));
             }
-            Op::Not => {
-                if let Some(vec) = self.stack.pop() {
-                    self.stack.push(
-                        vec.into_iter()
-                            .map(|x| if x == 0 { 1 } else { 0 })
-                            .collect(),
-                    );
-                    Ok(())
-                } else {
-                    Err("Must have an array on the stack to invert".into())
+            Op::Not => self.monadic(|x| Ok(Number::from(x.is_zero())))?,
+            Op::And => self.logical(|x, y| x && y)?,
+            Op::Or => self.logical(|x, y| x || y)?,
+            Op::Xor => self.logical(|x, y| x != y)?,
+            Op::Nand => self.logical(|x, y| !(x && y))?,
+            Op::BitAnd => self.dyadic(|x, y| x.bit_and(y, policy))?,
+            Op::BitOr => self.dyadic(|x, y| x.bit_or(y, policy))?,
+            Op::BitXor => self.dyadic(|x, y| x.bit_xor(y, policy))?,
+            Op::BitNot => self.monadic(|x| x.bit_not(policy))?,
+            Op::ShiftLeft => self.dyadic(|x, y| x.shift(y, policy))?,
+            Op::ShiftRight => self.dyadic(|x, y| x.shift(&y.neg(policy)?, policy))?,
+            Op::Encode => {
+                let (value, base) = self.pop_pair()?;
+                let radices = integers(&base)?;
+                if let [base] = radices.as_slice() {
+                    if base < &BigInt::from(2) {
+                        return Err(EvalError::domain_error("needs a base of at least 2"));
+                    }
                 }
+                let rows = value
+                    .data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, n)| {
+                        let n = n.as_number()?.to_integer()?;
+                        match radices.as_slice() {
+                            [base] => digits(n, base),
+                            radices => Ok(mixed_radix(n, radices)),
+                        }
+                        .map_err(|e| e.at_index(i))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+                let data = rows
+                    .into_iter()
+                    .flat_map(|row| iter::repeat_n(BigInt::zero(), width - row.len()).chain(row))
+                    .map(|digit| policy.resolve(digit).map(Element::Num))
+                    .collect::<Result<_, _>>()?;
+                let shape = if value.rank() == 1 && value.data.len() == 1 {
+                    vec![width]
+                } else {
+                    [&value.shape[..], &[width]].concat()
+                };
+                self.stack.push(Value::with_shape(shape, data));
+            }
+            Op::Decode => {
+                let (digits, base) = self.pop_pair()?;
+                let radices = integers(&base)?;
+                let rows = if digits.rank() > 1 {
+                    digits.cells()
+                } else {
+                    vec![&digits.data[..]]
+                };
+                let numbers = rows
+                    .into_iter()
+                    .map(|row| {
+                        if radices.len() != 1 && radices.len() != row.len() {
+                            return Err(EvalError::length_mismatch(row.len(), radices.len()));
+                        }
+                        let mut n = BigInt::zero();
+                        for (i, digit) in row.iter().enumerate() {
+                            let digit = digit
+                                .as_number()
+                                .and_then(Number::to_integer)
+                                .map_err(|e| e.at_index(i))?;
+                            n = n * &radices[i.min(radices.len() - 1)] + digit;
+                        }
+                        policy.resolve(n)
+                    })
+                    .collect::<Result<_, _>>()?;
+                self.stack.push(numbers);
             }
+            Op::Any => self.reduce(Number::Int(0), |x, y| {
+                let (x, y) = (x.to_bool()?, y.to_bool()?);
+                Ok(Number::from(x || y))
+            })?,
+            Op::All => self.reduce(Number::Int(1), |x, y| {
+                let (x, y) = (x.to_bool()?, y.to_bool()?);
+                Ok(Number::from(x && y))
+            })?,
             Op::Duplicate => {
-                if let Some(vec) = self.stack.last().cloned() {
-                    self.stack.push(vec);
-                    Ok(())
+                let value = self.pop()?;
+                self.stack.push(value.clone());
+                self.stack.push(value);
+            }
+            Op::Swap => {
+                let (a, b) = self.pop_pair()?;
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Over => {
+                let (a, b) = self.pop_pair()?;
+                self.stack.push(a.clone());
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+            Op::Rotate => {
+                let (b, c) = self.pop_pair()?;
+                let a = self.pop()?;
+                self.stack.push(b);
+                self.stack.push(c);
+                self.stack.push(a);
+            }
+            Op::Nip => {
+                let (_, b) = self.pop_pair()?;
+                self.stack.push(b);
+            }
+            Op::Pick => {
+                let depth = self.pop()?;
+                let n = depth
+                    .first_number()?
+                    .whole_i32("needs a whole-number depth")?;
+                let value = usize::try_from(n)
+                    .ok()
+                    .and_then(|n| self.stack.iter().rev().nth(n))
+                    .ok_or_else(|| EvalError::index_out_of_bounds(n, self.stack.len()))?;
+                self.stack.push(value.clone());
+            }
+            Op::Concatenate => {
+                let (a, b) = self.pop_pair()?;
+                let (a_rows, b_rows, tail) = if a.shape[1..] == b.shape[1..] {
+                    (a.len(), b.len(), &a.shape[1..])
+                } else if a.shape == b.shape[1..] {
+                    (1, b.len(), &b.shape[1..])
+                } else if b.shape == a.shape[1..] {
+                    (a.len(), 1, &a.shape[1..])
                 } else {
-                    Err("Stack is empty".into())
+                    // Line the lower-rank side up against the other's rows,
+                    // and point at the first axis where they differ
+                    let (a_row, b_row) = match a.rank().cmp(&b.rank()) {
+                        Ordering::Less => (&a.shape[..], &b.shape[1..]),
+                        Ordering::Equal => (&a.shape[1..], &b.shape[1..]),
+                        Ordering::Greater => (&a.shape[1..], &b.shape[..]),
+                    };
+                    return Err(match a_row.iter().zip(b_row).find(|(m, n)| m != n) {
+                        Some((&m, &n)) => EvalError::length_mismatch(m, n),
+                        None => EvalError::domain_error("needs rows of the same rank"),
+                    });
+                };
+                let shape = [&[a_rows + b_rows], tail].concat();
+                self.stack
+                    .push(Value::with_shape(shape, [a.data, b.data].concat()));
+            }
+            Op::RotateRows => {
+                let (array, count) = self.pop_pair()?;
+                let n = count
+                    .first_number()?
+                    .whole_i32("needs a whole-number count")?;
+                let mut cells = array.cells();
+                if !cells.is_empty() {
+                    let by = i64::from(n).rem_euclid(cells.len() as i64) as usize;
+                    cells.rotate_left(by);
                 }
+                self.stack.push(array.with_cells(&cells));
             }
-            Op::Concatenate => {
-                if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                    let mut result = a;
-                    result.extend(b);
-                    self.stack.push(result);
-                    Ok(())
+            Op::Windows | Op::Chunks => {
+                let (array, size) = self.pop_pair()?;
+                let n = size.first_number()?.whole_usize("needs a positive size")?;
+                if n == 0 {
+                    return Err(EvalError::domain_error("needs a positive size"));
+                }
+                let cells = array.cells();
+                let groups: Vec<&[&[Element]]> = if let Op::Windows = op {
+                    cells.windows(n).collect()
                 } else {
-                    Err("Must have two arrays on the stack to concatenate".into())
+                    cells.chunks(n).collect()
+                };
+                let rows = groups.iter().map(|rows| array.with_cells(rows)).collect();
+                self.stack.push(Value::from_rows(rows, self.max_depth)?);
+            }
+            Op::Partition => {
+                let (array, keys) = self.pop_pair()?;
+                if array.len() != keys.data.len() {
+                    return Err(EvalError::length_mismatch(array.len(), keys.data.len()));
+                }
+                let keys = keys
+                    .data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, key)| {
+                        key.as_number()
+                            .and_then(|k| k.whole_usize("needs non-negative whole-number keys"))
+                            .map_err(|e| e.at_index(i))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let cells = array.cells();
+                let mut groups: Vec<Vec<&[Element]>> = Vec::new();
+                for (i, (&key, cell)) in keys.iter().zip(&cells).enumerate() {
+                    if key == 0 {
+                        continue;
+                    }
+                    match groups.last_mut() {
+                        Some(group) if i > 0 && keys[i - 1] == key => group.push(cell),
+                        _ => groups.push(vec![cell]),
+                    }
                 }
+                let rows = groups.iter().map(|rows| array.with_cells(rows)).collect();
+                self.stack.push(Value::from_rows(rows, self.max_depth)?);
             }
             Op::Split => {
-                if let (Some(idx), Some(array)) = (self.stack.pop(), self.stack.pop()) {
-                    if let Some(i) = idx.first() {
-                        let i = *i as usize;
-                        if i <= array.len() {
-                            let (left, right) = array.split_at(i);
-                            self.stack.push(left.to_vec());
-                            self.stack.push(right.to_vec());
-                            Ok(())
-                        } else {
-                            Err("Split index out of bounds".into())
-                        }
-                    } else {
-                        Err("Split index array is empty".into())
-                    }
-                } else {
-                    Err("Must have an array and index on the stack to split".into())
+                let (array, idx) = self.pop_pair()?;
+                let i = idx
+                    .first_number()?
+                    .whole_i32("needs a whole-number index")?;
+                if i < 0 || i as usize > array.len() {
+                    return Err(EvalError::index_out_of_bounds(i, array.len()));
+                }
+                let cells = array.cells();
+                let (left, right) = cells.split_at(i as usize);
+                self.stack.push(array.with_cells(left));
+                self.stack.push(array.with_cells(right));
+            }
+            Op::Index => {
+                let (array, index) = self.pop_pair()?;
+                let mut value = array;
+                for (axis, i) in index.data.iter().enumerate() {
+                    let i = self.index(i, value.len()).map_err(|e| e.at_index(axis))?;
+                    value = value.row(i);
                 }
+                self.stack.push(value);
             }
-            Op::Array(nums) => {
-                self.stack.push(nums);
-                Ok(())
+            Op::Select => {
+                let (array, indices) = self.pop_pair()?;
+                let cells = array.cells();
+                let selected = indices
+                    .data
+                    .iter()
+                    .enumerate()
+                    .map(|(n, i)| {
+                        let i = self.index(i, cells.len()).map_err(|e| e.at_index(n))?;
+                        Ok(cells[i])
+                    })
+                    .collect::<Result<Vec<_>, EvalError>>()?;
+                let shape = if array.rank() > 1 {
+                    [&indices.shape[..], &array.shape[1..]].concat()
+                } else {
+                    indices.shape.clone()
+                };
+                self.stack.push(Value::with_shape(shape, selected.concat()));
             }
-            Op::PopLeft => {
-                if let Some(mut vec) = self.stack.pop() {
-                    if !vec.is_empty() {
-                        let first = vec.remove(0);
-                        self.stack.push(vec);
-                        self.stack.push(vec![first]);
-                        Ok(())
-                    } else {
-                        Err("Cannot pop from empty array".into())
-                    }
+            Op::Take => {
+                let (array, count) = self.pop_pair()?;
+                let cells = array.cells();
+                let (n, k) = Self::count(&count, cells.len())?;
+                let kept = if n < 0 {
+                    &cells[cells.len() - k..]
+                } else {
+                    &cells[..k]
+                };
+                self.stack.push(array.with_cells(kept));
+            }
+            Op::Skip => {
+                let (array, count) = self.pop_pair()?;
+                let cells = array.cells();
+                let (n, k) = Self::count(&count, cells.len())?;
+                let kept = if n < 0 {
+                    &cells[..cells.len() - k]
                 } else {
-                    Err("Must have an array on the stack".into())
+                    &cells[k..]
+                };
+                self.stack.push(array.with_cells(kept));
+            }
+            Op::PopLeft => {
+                let value = self.pop()?;
+                if value.is_empty() {
+                    return Err(EvalError::empty_operand());
                 }
+                self.stack.push(value.with_cells(&value.cells()[1..]));
+                self.stack.push(value.cell(0));
             }
             Op::PopRight => {
-                if let Some(mut vec) = self.stack.pop() {
-                    if let Some(last) = vec.pop() {
-                        self.stack.push(vec);
-                        self.stack.push(vec![last]);
-                        Ok(())
-                    } else {
-                        Err("Cannot pop from empty array".into())
-                    }
-                } else {
-                    Err("Must have an array on the stack".into())
+                let value = self.pop()?;
+                if value.is_empty() {
+                    return Err(EvalError::empty_operand());
                 }
+                let last = value.len() - 1;
+                self.stack.push(value.with_cells(&value.cells()[..last]));
+                self.stack.push(value.cell(last));
             }
             Op::AppendLeft => {
-                if let (Some(element), Some(mut array)) = (self.stack.pop(), self.stack.pop()) {
-                    if let Some(&value) = element.first() {
-                        array.insert(0, value);
-                        self.stack.push(array);
-                        Ok(())
-                    } else {
-                        Err("Element array is empty".into())
-                    }
-                } else {
-                    Err("Must have an array and element on the stack".into())
-                }
+                let (array, element) = self.pop_pair()?;
+                let row = Self::row_of(&array, &element)?;
+                let cells = [&[row][..], &array.cells()].concat();
+                self.stack.push(array.with_cells(&cells));
             }
             Op::AppendRight => {
-                if let (Some(element), Some(mut array)) = (self.stack.pop(), self.stack.pop()) {
-                    if let Some(&value) = element.first() {
-                        array.push(value);
-                        self.stack.push(array);
-                        Ok(())
-                    } else {
-                        Err("Element array is empty".into())
-                    }
-                } else {
-                    Err("Must have an array and element on the stack".into())
+                let (array, element) = self.pop_pair()?;
+                let row = Self::row_of(&array, &element)?;
+                let cells = [&array.cells(), &[row][..]].concat();
+                self.stack.push(array.with_cells(&cells));
+            }
+            Op::Box => {
+                let value = self.pop()?;
+                self.stack.push(Value::scalar(value.boxed(self.max_depth)?));
+            }
+            Op::Shape => {
+                let value = self.pop()?;
+                let shape = value
+                    .shape
+                    .iter()
+                    .map(|&n| i32::try_from(n).map(Number::Int))
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| EvalError::overflow())?;
+                self.stack.push(shape);
+            }
+            Op::Reshape => {
+                let (value, shape) = self.pop_pair()?;
+                let shape = Self::shape_of(&shape)?;
+                self.stack.push(value.reshape(shape)?);
+            }
+            Op::Ravel => {
+                let value = self.pop()?;
+                self.stack.push(Value::new(value.data));
+            }
+            Op::Transpose => {
+                let value = self.pop()?;
+                self.stack.push(value.transpose());
+            }
+            Op::CodePoints => self.monadic_atoms(|x| match x {
+                Element::Char(c) => Ok(Element::Num(Number::Int(*c as i32))),
+                _ => Err(EvalError::domain_error("expected a character")),
+            })?,
+            Op::Characters => self.monadic_atoms(|x| {
+                x.as_number()?
+                    .to_i32()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .and_then(char::from_u32)
+                    .map(Element::Char)
+                    .ok_or_else(|| EvalError::domain_error("expected a valid code point"))
+            })?,
+            Op::Unbox => {
+                let value = self.pop()?;
+                for element in value.data {
+                    self.stack.push(match element {
+                        Element::Boxed(inner) => inner,
+                        num => Value::scalar(num),
+                    });
                 }
             }
+            Op::Name(_)
+            | Op::Apply
+            | Op::Each
+            | Op::Reduce
+            | Op::Scan
+            | Op::If
+            | Op::Switch
+            | Op::Repeat
+            | Op::DoWhile
+            | Op::Converge
+            | Op::Dip => unreachable!("{op} runs code, so `control` applies it"),
         }
+        Ok(())
     }
 
-    pub fn evaluate_sequence(&mut self, ops: Vec<Op>) {
-        for op in ops {
-            if let Err(e) = self.evaluate(op) {
-                println!("Error: {e}");
-                break;
+    /// Evaluates a whole program as one unit: if any instruction fails, the
+    /// stack, words and variables are left as they were before the program
+    /// started.
+    pub fn evaluate_sequence(&mut self, program: Program) -> Result<(), EvalError> {
+        let stack = self.stack.clone();
+        let words = self.words.clone();
+        let variables = self.variables.clone();
+        let result = program
+            .into_iter()
+            .try_for_each(|instruction| self.evaluate(instruction));
+        if result.is_err() {
+            self.stack = stack;
+            self.words = words;
+            self.variables = variables;
+        }
+        result
+    }
+
+    pub fn parse(input: &str) -> Result<Program, Vec<ParseError>> {
+        parser::parse(input)
+    }
+
+    fn pop(&mut self) -> Result<Value, EvalError> {
+        self.stack
+            .pop()
+            .ok_or_else(|| EvalError::stack_underflow(0))
+    }
+
+    /// Pops a value and pushes the result of applying `f` to every number in it.
+    fn monadic(
+        &mut self,
+        f: impl Fn(&Number) -> Result<Number, EvalError>,
+    ) -> Result<(), EvalError> {
+        self.monadic_atoms(|x| f(x.as_number()?).map(Element::Num))
+    }
+
+    /// Like `monadic`, but `f` sees every number and character as is.
+    fn monadic_atoms(
+        &mut self,
+        f: impl Fn(&Element) -> Result<Element, EvalError>,
+    ) -> Result<(), EvalError> {
+        let value = self.pop()?;
+        let data = value
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, x)| pervade_monadic(x, &f).map_err(|e| e.at_index(i)))
+            .collect::<Result<_, _>>()?;
+        self.stack.push(Value::with_shape(value.shape, data));
+        Ok(())
+    }
+
+    /// Folds the rows of a value together with `f`, so a list reduces to a
+    /// single number and a matrix reduces to one row.
+    fn reduce(
+        &mut self,
+        identity: Number,
+        f: impl Fn(&Number, &Number) -> Result<Number, EvalError>,
+    ) -> Result<(), EvalError> {
+        let value = self.pop()?;
+        let f = numeric(f);
+        let mut acc = vec![Element::Num(identity); value.cell_len()];
+        for cell in value.cells() {
+            for (i, (a, x)) in acc.iter_mut().zip(cell).enumerate() {
+                *a = pervade(a, x, &f).map_err(|e| e.at_index(i))?;
             }
         }
+        self.stack.push(Value::with_shape(value.cell_shape(), acc));
+        Ok(())
     }
 
-    pub fn parse(input: &str) -> Vec<Op> {
-        let mut ops = Vec::new();
-        let mut chars = input.chars().peekable();
-        let mut current_array: Option<Vec<i32>> = None;
-        let mut current_num = String::new();
+    /// Looks a binding up in the running word, then among the variables
+    /// made outside any word.
+    fn binding(&self, name: &str) -> Option<&Value> {
+        self.locals
+            .last()
+            .and_then(|scope| scope.get(name))
+            .or_else(|| self.variables.get(name))
+    }
 
-        while let Some(c) = chars.next() {
-            match c {
-                '#' => {
-                    // Skip the rest of the line
-                    while let Some(c) = chars.next() {
-                        if c == '\n' {
-                            break;
-                        }
-                    }
-                }
-                '[' => {
-                    current_array = Some(Vec::new());
-                    current_num.clear();
-                }
-                ']' => {
-                    if !current_num.is_empty() {
-                        if let Ok(n) = current_num.parse() {
-                            if let Some(array) = &mut current_array {
-                                array.push(n);
-                            }
-                        }
-                        current_num.clear();
-                    }
-                    if let Some(array) = current_array.take() {
-                        if array.len() == 1 {
-                            ops.push(Op::Number(array[0]));
-                        } else {
-                            ops.push(Op::Array(array));
-                        }
-                    }
-                }
-                c if c.is_whitespace() => {
-                    if !current_num.is_empty() {
-                        if let Ok(n) = current_num.parse() {
-                            if let Some(array) = &mut current_array {
-                                array.push(n);
-                            } else {
-                                ops.push(Op::Number(n));
-                            }
-                        }
-                        current_num.clear();
-                    }
-                }
-                d if d.is_ascii_digit() || d == '-' => {
-                    current_num.push(d);
-                }
-                op => {
-                    if !current_num.is_empty() {
-                        if let Ok(n) = current_num.parse() {
-                            if let Some(array) = &mut current_array {
-                                array.push(n);
-                            } else {
-                                ops.push(Op::Number(n));
-                            }
-                        }
-                        current_num.clear();
+    /// Runs a quotation on `args`, returning the one value it must leave in
+    /// their place.
+    fn call(&mut self, quotation: &Quotation, args: Vec<Value>) -> Result<Value, EvalError> {
+        let n = args.len();
+        let base = self.stack.len();
+        self.stack.extend(args);
+        self.run(quotation)?;
+        if self.stack.len() != base + 1 {
+            return Err(EvalError::domain_error(format!(
+                "needs a quotation that turns {n} value(s) into one"
+            )));
+        }
+        self.pop()
+    }
+
+    /// Checks that `element` is a whole number indexing into `len` rows,
+    /// and turns it into a position counting from 0.
+    fn index(&self, element: &Element, len: usize) -> Result<usize, EvalError> {
+        let i = element
+            .as_number()?
+            .whole_i32("needs whole-number indices")?;
+        (i64::from(i) - self.index_origin.offset() as i64)
+            .try_into()
+            .ok()
+            .filter(|&i: &usize| i < len)
+            .ok_or_else(|| EvalError::index_out_of_bounds(i, len))
+    }
+
+    /// A list of row indices, given as positions counting from 0.
+    fn indices(&self, indices: impl IntoIterator<Item = usize>) -> Result<Value, EvalError> {
+        naturals(indices, self.index_origin.offset())
+    }
+
+    /// The non-negative whole numbers in `value`, as taken by `⍴` and `⍳`.
+    fn shape_of(value: &Value) -> Result<Vec<usize>, EvalError> {
+        value
+            .data
+            .iter()
+            .map(|n| {
+                n.as_number()?
+                    .whole_usize("needs non-negative whole numbers")
+            })
+            .collect()
+    }
+
+    /// The count given to `↙`/`↘`, along with how many rows it covers. A
+    /// negative count counts from the end.
+    fn count(count: &Value, len: usize) -> Result<(i32, usize), EvalError> {
+        let n = count
+            .first_number()?
+            .whole_i32("needs a whole-number count")?;
+        let k = n.unsigned_abs() as usize;
+        if k > len {
+            return Err(EvalError::index_out_of_bounds(n, len));
+        }
+        Ok((n, k))
+    }
+
+    /// The quotation held by a single-element value.
+    fn quotation_of(value: &Value) -> Result<Quotation, EvalError> {
+        match value.data.as_slice() {
+            [Element::Quotation(quotation)] => Ok(quotation.clone()),
+            _ => Err(EvalError::domain_error("expected a quotation")),
+        }
+    }
+
+    /// The row that `⊲`/`⊳` add to `array`: the first element for a list, or
+    /// a whole row for higher ranks.
+    /// Splits what `⊗` and `⌗` look for into rows shaped like those of the
+    /// array being searched, along with the shape of the result. A single
+    /// row of a matrix is looked for as a whole.
+    fn search_keys<'a>(
+        haystack: &Value,
+        needles: &'a Value,
+    ) -> Result<(Vec<usize>, Vec<&'a [Element]>), EvalError> {
+        let row_shape = &haystack.shape[1..];
+        let shape = match needles.shape.strip_suffix(row_shape) {
+            Some([]) => vec![1],
+            Some(outer) => outer.to_vec(),
+            None => {
+                let trailing = needles.rank().saturating_sub(row_shape.len());
+                return Err(EvalError::length_mismatch(
+                    haystack.cell_len(),
+                    needles.shape[trailing..].iter().product(),
+                ));
+            }
+        };
+        let n = haystack.cell_len();
+        let keys = (0..shape.iter().product())
+            .map(|i| &needles.data[i * n..(i + 1) * n])
+            .collect();
+        Ok((shape, keys))
+    }
+
+    fn row_of<'a>(array: &Value, element: &'a Value) -> Result<&'a [Element], EvalError> {
+        if array.rank() == 1 {
+            if element.data.is_empty() {
+                return Err(EvalError::empty_operand());
+            }
+            Ok(&element.data[..1])
+        } else if element.data.len() == array.cell_len() {
+            Ok(&element.data)
+        } else {
+            Err(EvalError::length_mismatch(
+                array.cell_len(),
+                element.data.len(),
+            ))
+        }
+    }
+
+    /// Pops two values and pushes the result of combining them elementwise.
+    fn dyadic(
+        &mut self,
+        f: impl Fn(&Number, &Number) -> Result<Number, EvalError>,
+    ) -> Result<(), EvalError> {
+        self.dyadic_atoms(numeric(f))
+    }
+
+    /// Combines two boolean arrays elementwise. Anything other than `0` or
+    /// `1` is a domain error.
+    fn logical(&mut self, f: fn(bool, bool) -> bool) -> Result<(), EvalError> {
+        self.dyadic(|x, y| Ok(Number::from(f(x.to_bool()?, y.to_bool()?))))
+    }
+
+    /// Orders two arrays elementwise, pushing whether `f` holds for each pair.
+    fn compare(&mut self, f: fn(Ordering) -> bool) -> Result<(), EvalError> {
+        self.dyadic_atoms(|x, y| Ok(Element::from(f(order(x, y)?))))
+    }
+
+    /// Like `dyadic`, but `f` sees every number and character as is.
+    fn dyadic_atoms(
+        &mut self,
+        f: impl Fn(&Element, &Element) -> Result<Element, EvalError>,
+    ) -> Result<(), EvalError> {
+        let (a, b) = self.pop_pair()?;
+        self.stack
+            .push(broadcast(&a, &b, |x, y| pervade(x, y, &f))?);
+        Ok(())
+    }
+
+    /// Pops the top two items, returning them in the order they were pushed.
+    fn pop_pair(&mut self) -> Result<(Value, Value), EvalError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        Ok((a, b))
+    }
+}
+
+/// A list of counts or indices, each shifted up by `offset`.
+fn naturals(values: impl IntoIterator<Item = usize>, offset: usize) -> Result<Value, EvalError> {
+    values
+        .into_iter()
+        .map(|n| {
+            n.checked_add(offset)
+                .and_then(|n| i32::try_from(n).ok())
+                .map(Number::Int)
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(EvalError::overflow)
+}
+
+/// The numbers from `start` to `stop` inclusive, counting by `step`. Ranges
+/// of integers stay integers.
+fn range(start: &Number, stop: &Number, step: &Number) -> Result<Value, EvalError> {
+    if step.is_zero() {
+        return Err(EvalError::domain_error("needs a non-zero step"));
+    }
+    let whole = |n: &Number| n.to_i32().filter(|_| !n.is_float()).map(i64::from);
+    if let (Some(a), Some(b), Some(s)) = (whole(start), whole(stop), whole(step)) {
+        let count = if (b - a) * s.signum() < 0 {
+            0
+        } else {
+            Value::size(&[((b - a) / s + 1) as usize])? as i64
+        };
+        // Every element lies between start and stop, so it fits in an i32
+        return Ok((0..count)
+            .map(|i| Number::Int((a + i * s) as i32))
+            .collect());
+    }
+
+    let (a, b, s) = (start.to_f64(), stop.to_f64(), step.to_f64());
+    if !(a.is_finite() && b.is_finite() && s.is_finite()) {
+        return Err(EvalError::domain_error("needs finite bounds"));
+    }
+    // Allow for rounding, so that [0 0.3 0.1] includes 0.3
+    let steps = ((b - a) / s + 1e-9).floor();
+    // Adding one in floating point can't overflow, and a count too big for a
+    // usize saturates, which `size` then rejects
+    let count = if steps < 0.0 { 0.0 } else { steps + 1.0 };
+    let count = Value::size(&[count as usize])?;
+    Ok((0..count)
+        .map(|i| Number::Float(a + i as f64 * s))
+        .collect())
+}
+
+/// Every element of a value as an exact integer.
+fn integers(value: &Value) -> Result<Vec<BigInt>, EvalError> {
+    value
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            x.as_number()
+                .and_then(Number::to_integer)
+                .map_err(|e| e.at_index(i))
+        })
+        .collect()
+}
+
+/// The digits of `n` in `base`, most significant first, using as many as
+/// needed.
+fn digits(mut n: BigInt, base: &BigInt) -> Result<Vec<BigInt>, EvalError> {
+    if n.is_negative() {
+        return Err(EvalError::domain_error(
+            "can't encode a negative number in a single base",
+        ));
+    }
+    let mut digits = Vec::new();
+    loop {
+        let (rest, digit) = n.div_mod_floor(base);
+        digits.push(digit);
+        n = rest;
+        if n.is_zero() {
+            break;
+        }
+    }
+    digits.reverse();
+    Ok(digits)
+}
+
+/// The digits of `n` in a mixed radix such as `[24 60 60]`, one per radix.
+/// As in APL, a radix of 0 takes whatever is left, and negative numbers wrap
+/// around.
+fn mixed_radix(mut n: BigInt, radices: &[BigInt]) -> Vec<BigInt> {
+    let mut digits: Vec<BigInt> = radices
+        .iter()
+        .rev()
+        .map(|radix| {
+            if radix.is_zero() {
+                std::mem::take(&mut n)
+            } else {
+                let (rest, digit) = n.div_mod_floor(radix);
+                n = rest;
+                digit
+            }
+        })
+        .collect();
+    digits.reverse();
+    digits
+}
+
+/// Combines two values elementwise. A single element is extended to match
+/// the other side, and a value whose shape is a prefix of the other's is
+/// extended along the trailing axes (so a list of 2 can be added to each row
+/// of a 2×3 matrix). Otherwise both shapes must match.
+fn broadcast(
+    a: &Value,
+    b: &Value,
+    f: impl Fn(&Element, &Element) -> Result<Element, EvalError>,
+) -> Result<Value, EvalError> {
+    let (shape, data) = if a.data.len() == 1 {
+        (
+            &b.shape,
+            zip_indexed(b.data.len(), |i| f(&a.data[0], &b.data[i]))?,
+        )
+    } else if b.data.len() == 1 {
+        (
+            &a.shape,
+            zip_indexed(a.data.len(), |i| f(&a.data[i], &b.data[0]))?,
+        )
+    } else if a.shape == b.shape {
+        (
+            &a.shape,
+            zip_indexed(a.data.len(), |i| f(&a.data[i], &b.data[i]))?,
+        )
+    } else if b.shape.starts_with(&a.shape) {
+        let n = b.data.len() / a.data.len().max(1);
+        (
+            &b.shape,
+            zip_indexed(b.data.len(), |i| f(&a.data[i / n], &b.data[i]))?,
+        )
+    } else if a.shape.starts_with(&b.shape) {
+        let n = a.data.len() / b.data.len().max(1);
+        (
+            &a.shape,
+            zip_indexed(a.data.len(), |i| f(&a.data[i], &b.data[i / n]))?,
+        )
+    } else {
+        let (m, n) = a
+            .shape
+            .iter()
+            .zip(&b.shape)
+            .find(|(m, n)| m != n)
+            .map_or((a.len(), b.len()), |(&m, &n)| (m, n));
+        return Err(EvalError::length_mismatch(m, n));
+    };
+    Ok(Value::with_shape(shape.clone(), data))
+}
+
+fn zip_indexed(
+    len: usize,
+    f: impl Fn(usize) -> Result<Element, EvalError>,
+) -> Result<Vec<Element>, EvalError> {
+    (0..len).map(|i| f(i).map_err(|e| e.at_index(i))).collect()
+}
+
+/// Applies a function to two elements, reaching into boxes so that
+/// `[[1 2] [3]] 1 +` gives `[[2, 3], [4]]`. `f` only ever sees numbers and
+/// characters.
+fn pervade<F>(a: &Element, b: &Element, f: &F) -> Result<Element, EvalError>
+where
+    F: Fn(&Element, &Element) -> Result<Element, EvalError>,
+{
+    match (a, b) {
+        (Element::Boxed(x), Element::Boxed(y)) => {
+            broadcast(x, y, |x, y| pervade(x, y, f)).map(Element::Boxed)
+        }
+        (Element::Boxed(x), y) => {
+            broadcast(x, &Value::scalar(y.clone()), |x, y| pervade(x, y, f)).map(Element::Boxed)
+        }
+        (x, Element::Boxed(y)) => {
+            broadcast(&Value::scalar(x.clone()), y, |x, y| pervade(x, y, f)).map(Element::Boxed)
+        }
+        (x, y) => f(x, y),
+    }
+}
+
+fn pervade_monadic<F>(a: &Element, f: &F) -> Result<Element, EvalError>
+where
+    F: Fn(&Element) -> Result<Element, EvalError>,
+{
+    match a {
+        Element::Boxed(x) => {
+            let data = x
+                .data
+                .iter()
+                .map(|x| pervade_monadic(x, f))
+                .collect::<Result<_, _>>()?;
+            Ok(Element::Boxed(Value::with_shape(x.shape.clone(), data)))
+        }
+        x => f(x),
+    }
+}
+
+/// Orders two elements for the comparison glyphs and `⌊`/`⌈`. A number and
+/// a character can't be ordered against each other.
+fn order(x: &Element, y: &Element) -> Result<Ordering, EvalError> {
+    if mem::discriminant(x) != mem::discriminant(y) {
+        return Err(EvalError::domain_error(
+            "can only order elements of the same kind",
+        ));
+    }
+    Ok(x.cmp(y))
+}
+
+/// Lifts a function on numbers to one on elements, so that characters are
+/// rejected with a domain error.
+fn numeric<F>(f: F) -> impl Fn(&Element, &Element) -> Result<Element, EvalError>
+where
+    F: Fn(&Number, &Number) -> Result<Number, EvalError>,
+{
+    move |x, y| f(x.as_number()?, y.as_number()?).map(Element::Num)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `source` as one line and prints what's left on the stack.
+    fn eval(glyph: &mut Glyph, source: &str) -> Result<Vec<String>, EvalError> {
+        glyph.evaluate_sequence(Glyph::parse(source).expect("source should parse"))?;
+        Ok(glyph.stack.iter().map(Value::to_string).collect())
+    }
+
+    fn stack(source: &str) -> Vec<String> {
+        eval(&mut Glyph::new(), source).unwrap()
+    }
+
+    fn top(source: &str) -> String {
+        stack(source).pop().expect("stack should not be empty")
+    }
+
+    fn error(source: &str) -> EvalError {
+        eval(&mut Glyph::new(), source).unwrap_err()
+    }
+
+    /// Checks what each source leaves on top of the stack.
+    fn examples(examples: &[(&str, &str)]) {
+        for (source, expected) in examples {
+            assert_eq!(top(source), *expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn dyadic_glyphs_broadcast_single_values() {
+        examples(&[
+            ("[1] [2] +", "[3]"),
+            ("[1 2] [2 1] +", "[3, 3]"),
+            ("[1 2 3] 2 ×", "[2, 4, 6]"),
+            ("[2] [1 2 3] -", "[1, 0, -1]"),
+        ]);
+        for source in [
+            "[1 2] [1 2 3] -",
+            "[1 2] [1 2 3] =",
+            "[1 2] [1 0 1] ∧",
+            "[1 2] [1 2 3] ⌈",
+        ] {
+            assert!(
+                matches!(
+                    error(source),
+                    EvalError::LengthMismatch {
+                        left: 2,
+                        right: 3,
+                        ..
                     }
-                    match op {
-                        '↑' => ops.push(Op::Range),
-                        '+' => ops.push(Op::Add),
-                        '-' => {
-                            if chars.peek().map_or(false, |c| c.is_ascii_digit()) {
-                                current_num.push('-');
-                            } else {
-                                ops.push(Op::Subtract);
-                            }
-                        }
-                        '×' => ops.push(Op::Multiply),
-                        '/' => ops.push(Op::Divide),
-                        '%' => ops.push(Op::Modulo),
-                        '∑' => ops.push(Op::Sum),
-                        '∏' => ops.push(Op::Product),
-                        '↔' => ops.push(Op::Reverse),
-                        '⇕' => ops.push(Op::Sort),
-                        '⋕' => ops.push(Op::Length),
-                        '>' => ops.push(Op::Greater),
-                        '≥' => ops.push(Op::GreaterEqual),
-                        '<' => ops.push(Op::Less),
-                        '≤' => ops.push(Op::LessEqual),
-                        '=' => ops.push(Op::Equal),
-                        '∅' => ops.push(Op::Clear),
-                        '⊃' => ops.push(Op::Filter),
-                        '¬' => ops.push(Op::Not),
-                        '⊕' => ops.push(Op::Duplicate),
-                        '⋈' => ops.push(Op::Concatenate),
-                        '⋉' => ops.push(Op::Split),
-                        '⊣' => ops.push(Op::PopLeft),
-                        '⊢' => ops.push(Op::PopRight),
-                        '⊲' => ops.push(Op::AppendLeft),
-                        '⊳' => ops.push(Op::AppendRight),
-                        _ => {}
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn overflow_follows_the_policy() {
+        let source = "2147483647 1 +";
+        assert!(matches!(error(source), EvalError::Overflow { .. }));
+        for (policy, expected) in [
+            (OverflowPolicy::Wrapping, "[-2147483648]"),
+            (OverflowPolicy::Saturating, "[2147483647]"),
+            (OverflowPolicy::Promote, "[2147483648]"),
+        ] {
+            let mut glyph = Glyph::new().with_overflow(policy);
+            assert_eq!(eval(&mut glyph, source).unwrap(), [expected], "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn division_by_zero_follows_the_policy() {
+        assert!(matches!(
+            error("[6 1] [2 0] /"),
+            EvalError::DomainError { index: Some(1), .. }
+        ));
+        for (policy, expected) in [
+            (DivisionPolicy::Float, ["[3, inf]", "[NaN, NaN]"]),
+            (DivisionPolicy::Sentinel(0), ["[3, 0]", "[0, 0]"]),
+        ] {
+            let mut glyph = Glyph::new().with_division(policy);
+            assert_eq!(
+                eval(&mut glyph, "[6 1] [2 0] / [7 -7] 0 %").unwrap(),
+                expected,
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn floats_mix_with_integers() {
+        examples(&[
+            ("[1 2] 0.5 ×", "[0.5, 1]"),
+            ("6 2 /", "[3]"),
+            ("7 2 /", "[3.5]"),
+            ("¯2.5 0.5 +", "[-2]"),
+            ("1e-3 1000 ×", "[1]"),
+            ("[1 2.5] 1 =", "[1, 0]"),
+        ]);
+    }
+
+    #[test]
+    fn nested_arrays_hold_boxes() {
+        examples(&[
+            ("[[1 2] [3]] ⋕", "[2]"),
+            ("[1 2] □ [3] □ ⋈", "[[1, 2], [3]]"),
+            ("[[1 2] [3]] 1 +", "[[2, 3], [4]]"),
+            ("[[1 2] [3]] ↔", "[[3], [1, 2]]"),
+            ("[3 [1 2]] ⇕", "[3, [1, 2]]"),
+        ]);
+        assert_eq!(stack("[[1 2] [3]] ◇"), ["[1, 2]", "[3]"]);
+    }
+
+    #[test]
+    fn matrices_work_on_rows() {
+        examples(&[
+            ("6 ↑ [2 3] ⍴", "[[1, 2, 3],\n [4, 5, 6]]"),
+            ("6 ↑ [2 3] ⍴ △", "[2, 3]"),
+            ("6 ↑ [3 2] ⍴ ⍉ △", "[2, 3]"),
+            ("6 ↑ [2 3] ⍴ ♭", "[1, 2, 3, 4, 5, 6]"),
+            ("6 ↑ [2 3] ⍴ ⋕", "[2]"),
+            ("6 ↑ [3 2] ⍴ ↔", "[[5, 6],\n [3, 4],\n [1, 2]]"),
+            ("6 ↑ [2 3] ⍴ ∑", "[5, 7, 9]"),
+            ("6 ↑ [2 3] ⍴ [10 20] +", "[[11, 12, 13],\n [24, 25, 26]]"),
+        ]);
+    }
+
+    #[test]
+    fn strings_are_arrays_of_characters() {
+        examples(&[
+            (r#""hello" "l" ="#, "[0, 0, 1, 1, 0]"),
+            (r#""hello" ↔"#, r#""olleh""#),
+            (r#""ab" "cd" ⋈"#, r#""abcd""#),
+            (r#""" ⋕"#, "[0]"),
+            (r#""hi" ⌂"#, "[104, 105]"),
+            (r#""a\tb" ⌂"#, "[97, 9, 98]"),
+            (r#""hal" ⌂ 1 + ⌑"#, r#""ibm""#),
+        ]);
+    }
+
+    #[test]
+    fn quotations_run_with_bang() {
+        examples(&[
+            ("(2 ×)", "[(2 ×)]"),
+            ("[(1 +) (2 ×)]", "[(1 +), (2 ×)]"),
+            ("3 (2 ×) !", "[6]"),
+            ("1 2 (+) !", "[3]"),
+            ("2 ((3 ×) !) !", "[6]"),
+        ]);
+        assert_eq!(stack("1 2 (◌ ◌) !"), [] as [&str; 0]);
+        assert!(matches!(
+            error("1 (◌ ◌) !"),
+            EvalError::StackUnderflow { found: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn each_reduce_and_scan_run_quotations_on_rows() {
+        examples(&[
+            ("[1 2 3] (2 ×) ¨", "[2, 4, 6]"),
+            ("6 ↑ [2 3] ⍴ (∑) ¨", "[6, 15]"),
+            ("[1 2] (↑) ¨", "[[1], [1, 2]]"),
+            ("[[1 2] [3 4 5]] ⋕¨", "[2, 3]"),
+            ("[] (1 +) ¨", "[]"),
+            ("[1 2 3 4] (×) ⌿", "[24]"),
+            ("[1 2 3 4] +⌿", "[10]"),
+            ("6 ↑ [2 3] ⍴ +⌿", "[5, 7, 9]"),
+            ("[1 2 3 4] (+) ⍀", "[1, 3, 6, 10]"),
+            ("[] (+) ⍀", "[]"),
+            ("10 ↑ ⊕ 2 % ¬ ⊃ ∑", "[30]"),
+            ("5 ↑ ∏", "[120]"),
+        ]);
+        assert!(matches!(error("[] (+) ⌿"), EvalError::EmptyOperand { .. }));
+    }
+
+    #[test]
+    fn conditionals_pick_a_quotation() {
+        examples(&[
+            ("7 ⊕ 2 % (3 × 1 +) (2 /) ?", "[22]"),
+            ("2 (2) (3) ?", "[2]"),
+            ("[1 1 0] (2) (3) ?", "[3]"),
+            ("[] (2) (3) ?", "[3]"),
+            ("10 1 [(1 +) (2 ×)] ⁇", "[20]"),
+        ]);
+        assert!(matches!(
+            error(r#""a" (2) (3) ?"#),
+            EvalError::DomainError { .. }
+        ));
+        assert!(matches!(
+            error("10 2 [(1 +) (2 ×)] ⁇"),
+            EvalError::IndexOutOfBounds {
+                index: 2,
+                len: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn loops_repeat_quotations() {
+        examples(&[
+            ("1 10 (2 ×) ⍣", "[1024]"),
+            ("1 0 (2 ×) ⍣", "[1]"),
+            ("1 (2 × ⊕ 100 <) ⍢", "[128]"),
+            ("5 (0) ⍢", "[5]"),
+            ("100 (2 / ⌋) ⍥", "[0]"),
+        ]);
+        assert!(matches!(
+            error("1 -1 (2 ×) ⍣"),
+            EvalError::DomainError { .. }
+        ));
+        assert!(matches!(
+            error("1 100001 (1 +) ⍣"),
+            EvalError::IterationLimit { limit: 100_000, .. }
+        ));
+        assert!(matches!(error("(1) ⍢"), EvalError::IterationLimit { .. }));
+    }
+
+    #[test]
+    fn words_run_by_name() {
+        examples(&[
+            (": evens ⊕ 2 % ¬ ⊃ ; 10 ↑ evens", "[2, 4, 6, 8, 10]"),
+            (": fact ⊕ 1 > (⊕ 1 - fact ×) () ? ; 5 fact", "[120]"),
+            (": a 1 ; : a 2 ; a", "[2]"),
+            (": a b ; : b 7 ; a", "[7]"),
+        ]);
+        assert!(matches!(error("nope"), EvalError::DomainError { .. }));
+        let mut glyph = Glyph::new();
+        glyph.max_depth = 10;
+        assert!(matches!(
+            eval(&mut glyph, ": f f ; f"),
+            Err(EvalError::RecursionLimit { limit: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn variables_are_global_or_local_to_a_word() {
+        examples(&[
+            ("5 →x x x ×", "[25]"),
+            ("1 →x 2 →x x", "[2]"),
+            (": x 1 ; 2 →x x", "[2]"),
+            (
+                ": fib →n n 2 < (n) (n 1 - fib n 2 - fib +) ? ; 10 fib",
+                "[55]",
+            ),
+            ("0 →acc [1 2 3] (acc + →acc 0) ¨ acc", "[6]"),
+        ]);
+        assert_eq!(stack("1 →y : f 3 →y y ; f y"), ["[3]", "[1]"]);
+        assert!(matches!(
+            error(": f 3 →y ; f y"),
+            EvalError::DomainError { .. }
+        ));
+    }
+
+    #[test]
+    fn stack_glyphs_shuffle_the_stack() {
+        examples(&[("2 10 ⇄ /", "[5]"), ("1 2 3 1 ⇑", "[2]")]);
+        assert_eq!(stack("1 2 ⊕"), ["[1]", "[2]", "[2]"]);
+        assert_eq!(stack("1 2 ◌"), ["[1]"]);
+        assert_eq!(stack("1 2 ⇈"), ["[1]", "[2]", "[1]"]);
+        assert_eq!(stack("1 2 3 ⟲"), ["[2]", "[3]", "[1]"]);
+        assert_eq!(stack("1 2 ⊝"), ["[2]"]);
+        assert_eq!(stack("1 2 0 ⇑"), ["[1]", "[2]", "[2]"]);
+        assert_eq!(stack("1 2 10 (+) ⊙"), ["[3]", "[10]"]);
+        assert_eq!(stack("1 2 ∅"), [] as [&str; 0]);
+        assert!(matches!(
+            error("1 2 2 ⇑"),
+            EvalError::IndexOutOfBounds {
+                index: 2,
+                len: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn indexing_picks_rows() {
+        examples(&[
+            ("[10 20 30] 1 ⊡", "[20]"),
+            ("6 ↑ [2 3] ⍴ [1 2] ⊡", "[6]"),
+            ("[[1 2] [3]] 0 ⊡", "[1, 2]"),
+            ("[10 20 30] [2 0 0] ⊏", "[30, 10, 10]"),
+            ("[10 20 30] [] ⊏", "[]"),
+            ("6 ↑ [3 2] ⍴ [2 0] ⊏", "[[5, 6],\n [1, 2]]"),
+            ("[1 2 3 4] ¯1 ↙", "[4]"),
+            ("[1 2 3 4] 2 ↘", "[3, 4]"),
+            ("[1 2 3 4] ¯2 ↘", "[1, 2]"),
+        ]);
+        assert_eq!(stack("[1 2 3] 1 ⋉"), ["[1]", "[2, 3]"]);
+        for (source, index) in [
+            ("[10 20 30] 3 ⊡", 3),
+            ("[10 20 30] ¯1 ⊡", -1),
+            ("[1 2 3 4] 5 ↙", 5),
+        ] {
+            assert!(
+                matches!(
+                    error(source),
+                    EvalError::IndexOutOfBounds { index: i, .. } if i == index
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn grades_and_sorts() {
+        examples(&[
+            ("[3 1 2] ⇕", "[1, 2, 3]"),
+            ("[3 1 2] ⇵", "[3, 2, 1]"),
+            ("[3 1 2] ⍋", "[1, 2, 0]"),
+            ("[3 1 2] ⊕ ⍋ ⊏", "[1, 2, 3]"),
+            ("[3 1 2] ⍒", "[0, 2, 1]"),
+            ("[2 1 2 1] ⍋", "[1, 3, 0, 2]"),
+            ("[2 1 2 1] ⍒", "[0, 2, 1, 3]"),
+            ("[3 1 4 1 5 9 2 6] [3 3] ⍴ ⍋", "[1, 2, 0]"),
+            (r#""bca" [3 1 2] ⋔"#, r#""cab""#),
+        ]);
+        assert!(matches!(
+            error(r#""abc" [2 1] ⋔"#),
+            EvalError::LengthMismatch {
+                left: 3,
+                right: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn set_glyphs_work_on_rows() {
+        examples(&[
+            ("[3 1 3 2 1] ◴", "[3, 1, 2]"),
+            (r#""hello" ◴"#, r#""helo""#),
+            ("[1 2 1 2 3 4] [3 2] ⍴ ◴", "[[1, 2],\n [3, 4]]"),
+            ("[3 1 3 2 1] ◰", "[1, 1, 0, 1, 0]"),
+            ("[1 2 2 3] [3 4] ∪", "[1, 2, 3, 4]"),
+            ("[1 2 2 3] [3 2 4] ∩", "[2, 3]"),
+            ("[1 2 2 3] [3] ∖", "[1, 2]"),
+            ("[1 2 3 4] [2 2] ⍴ [3 4] ∖", "[[1, 2]]"),
+            ("[1 2 3 4 5] ⊕ [2 4] ∊ ⊃", "[2, 4]"),
+            ("[5 6 7] [7 5 9] ⊗", "[2, 0, 3]"),
+            (r#""mississippi" "sp" ⌗"#, "[4, 2]"),
+        ]);
+        assert!(matches!(
+            error("[1 2 3 4] [2 2] ⍴ [1 2 3] ∪"),
+            EvalError::LengthMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn scalar_math_glyphs() {
+        examples(&[
+            ("2 10 *", "[1024]"),
+            ("2 -1 *", "[0.5]"),
+            ("[3 1 4] [2 5 1] ⌈", "[3, 5, 4]"),
+            ("[3 1 4 1 5] ⌈⌿", "[5]"),
+            ("[3 1 4 1 5] ⌊⌿", "[1]"),
+            ("[12 18] 8 ⊓", "[4, 2]"),
+            ("[4 6] 10 ⊔", "[20, 30]"),
+            ("5 ¯", "[-5]"),
+            ("[-3 3] ⌵", "[3, 3]"),
+            ("[-2 0 3] ±", "[-1, 0, 1]"),
+            ("[-1.5 2.5] ⌋", "[-2, 2]"),
+            ("[-1.5 2.5] ⌉", "[-1, 3]"),
+            ("17 √", "[4]"),
+        ]);
+        assert!(matches!(
+            error("[12 2.5] 8 ⊓"),
+            EvalError::DomainError { index: Some(1), .. }
+        ));
+    }
+
+    #[test]
+    fn boolean_glyphs() {
+        examples(&[
+            ("[1 2] [2 1] >", "[0, 1]"),
+            ("10 ↑ ⊕ ⊕ 3 > ⇄ 8 < ∧ ⊃", "[4, 5, 6, 7]"),
+            ("[1 0] [1 1] ∨", "[1, 1]"),
+            ("[1 0] [1 1] ⊻", "[0, 1]"),
+            ("[1 0] [1 1] ⊼", "[0, 1]"),
+            ("[1 0] ¬", "[0, 1]"),
+            ("[0 0 1] ∃", "[1]"),
+            ("[0 0 1] ∀", "[0]"),
+            ("[] ∃", "[0]"),
+            ("[] ∀", "[1]"),
+        ]);
+    }
+
+    #[test]
+    fn bitwise_glyphs_and_base_conversion() {
+        examples(&[
+            ("0 ~", "[-1]"),
+            ("12 10 &", "[8]"),
+            ("12 10 |", "[14]"),
+            ("12 10 ^", "[6]"),
+            ("1 [0 4] «", "[1, 16]"),
+            ("-16 2 »", "[-4]"),
+            ("255 16 ⊤", "[15, 15]"),
+            ("0 2 ⊤", "[0]"),
+            ("3661 [24 60 60] ⊤", "[1, 1, 1]"),
+            ("-1 [2 2 2 2] ⊤", "[1, 1, 1, 1]"),
+            ("[1 0 1] 2 ⊥", "[5]"),
+            ("6 ↑ [2 3] ⍴ 2 ⊥", "[11, 32]"),
+            ("[1 1 1] [24 60 60] ⊥", "[3661]"),
+        ]);
+        assert!(matches!(error("1.5 ~"), EvalError::DomainError { .. }));
+        assert!(matches!(error("1 31 «"), EvalError::Overflow { .. }));
+    }
+
+    #[test]
+    fn ranges_and_indices() {
+        examples(&[
+            ("5 ↑", "[1, 2, 3, 4, 5]"),
+            ("-3 ↑", "[-1, -2, -3]"),
+            ("0 ↑", "[]"),
+            ("[5 2] ↑", "[5, 4, 3, 2]"),
+            ("[1 10 3] ↑", "[1, 4, 7, 10]"),
+            ("[0 1 0.25] ↑", "[0, 0.25, 0.5, 0.75, 1]"),
+            ("5 ⍳", "[0, 1, 2, 3, 4]"),
+            ("0 ⍳", "[]"),
+            ("[2 3] ⍳ △", "[2, 3, 2]"),
+            ("[2 3] ⍳ [0 0] ⊡", "[0, 0]"),
+            ("[2 3] ⍳ [1 2] ⊡", "[1, 2]"),
+        ]);
+        for source in ["[1 2 0] ↑", "2.5 ↑"] {
+            assert!(
+                matches!(error(source), EvalError::DomainError { .. }),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn rotate_windows_and_chunks() {
+        examples(&[
+            ("[1 2 3 4 5] 2 ⌽", "[3, 4, 5, 1, 2]"),
+            ("[1 2 3 4 5] ¯1 ⌽", "[5, 1, 2, 3, 4]"),
+            ("6 ↑ [3 2] ⍴ 1 ⌽", "[[3, 4],\n [5, 6],\n [1, 2]]"),
+            ("[1 2 3 4 5] 3 ◫ (∑ 3 /) ¨", "[2, 3, 4]"),
+            ("[1 2 3] 5 ◫", "[]"),
+            ("[1 2 3 4 5] 2 ⧉", "[[1, 2], [3, 4], [5]]"),
+            (r#""hi there" ⊕ " " = ¬ ⊜"#, r#"["hi", "there"]"#),
+            ("[1 2 3] [1 1 2] ⊜", "[[1, 2], [3]]"),
+        ]);
+        assert!(matches!(
+            error("[1 2 3] 0 ⧉"),
+            EvalError::DomainError { .. }
+        ));
+        assert!(matches!(
+            error("[1 2 3] [1 -1 1] ⊜"),
+            EvalError::DomainError { index: Some(1), .. }
+        ));
+    }
+
+    #[test]
+    fn promote_keeps_big_results() {
+        let mut glyph = Glyph::new().with_overflow(OverflowPolicy::Promote);
+        assert_eq!(eval(&mut glyph, "13 ↑ ∏").unwrap(), ["[6227020800]"]);
+        assert!(matches!(error("13 ↑ ∏"), EvalError::Overflow { .. }));
+        assert_eq!(
+            eval(&mut glyph, "∅ 99999999999 1 +").unwrap(),
+            ["[100000000000]"]
+        );
+        assert_eq!(top("99999999999"), "[99999999999]");
+        assert!(matches!(
+            error("99999999999 1 +"),
+            EvalError::Overflow { .. }
+        ));
+    }
+
+    #[test]
+    fn failed_line_leaves_everything_as_it_was() {
+        let mut glyph = Glyph::new();
+        eval(&mut glyph, "1 2 5 →x").unwrap();
+        let error = eval(&mut glyph, ": w 1 ; 7 →x ◌ 0 /").unwrap_err();
+        assert!(matches!(error, EvalError::DomainError { .. }));
+        assert_eq!(
+            glyph.stack,
+            [Value::scalar(Number::Int(1)), Value::scalar(Number::Int(2))]
+        );
+        assert!(glyph.words.is_empty());
+        assert_eq!(glyph.variables["x"], Value::scalar(Number::Int(5)));
+    }
+
+    #[test]
+    fn failed_instruction_restores_its_operands() {
+        let mut glyph = Glyph::new();
+        for instruction in Glyph::parse("1 [1 2] [1 2 3] +").unwrap() {
+            let _ = glyph.evaluate(instruction);
+        }
+        assert_eq!(glyph.stack.len(), 3);
+        for source in ["1 2 3 (◌ ◌ ◌ 1 0 /) !", "1 2 3 (◌ (◌ (◌ 1 0 /) !) !) !"] {
+            let mut glyph = Glyph::new();
+            for instruction in Glyph::parse(source).unwrap() {
+                let _ = glyph.evaluate(instruction);
+            }
+            assert_eq!(glyph.stack.len(), 4, "{source}");
+            assert_eq!(glyph.stack[0], Value::scalar(Number::Int(1)), "{source}");
+        }
+    }
+
+    #[test]
+    fn errors_carry_op_span_and_operands() {
+        let error = error("1 [1 2] [1 2 3] +");
+        assert_eq!(
+            error.to_string(),
+            "1:17: '+' needs arrays of the same length, got 2 and 3 (operands: [1, 2] [1, 2, 3])"
+        );
+        let error = self::error("[1 2 0] 1 ⇄ /");
+        assert!(matches!(
+            error,
+            EvalError::DomainError { index: Some(2), .. }
+        ));
+        assert!(matches!(
+            self::error("+"),
+            EvalError::StackUnderflow { found: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn errors_in_quotations_point_at_where_they_ran() {
+        let mut glyph = Glyph::new();
+        eval(&mut glyph, "                    (1 +) →q : w ⊕ ⊕ 1 + ;").unwrap();
+        for (source, column) in [("q !", 3), ("∅ w", 3), ("∅ [1] (w) ¨", 11)] {
+            let error = eval(&mut glyph, source).unwrap_err();
+            let span = error.context().span.unwrap();
+            assert_eq!(span.column, column, "{source}");
+            assert!(source.get(span.start..span.end).is_some(), "{source}");
+        }
+    }
+
+    #[test]
+    fn boxes_nest_at_most_max_depth_deep() {
+        assert!(matches!(
+            error("1 10000 (□) ⍣ ◌"),
+            EvalError::NestingLimit {
+                limit: DEFAULT_MAX_DEPTH,
+                ..
+            }
+        ));
+        let deepest = top(&format!("1 {DEFAULT_MAX_DEPTH} (□) ⍣"));
+        assert!(deepest.starts_with("[[[") && deepest.ends_with("]]]"));
+        let mut glyph = Glyph::new();
+        glyph.max_depth = 2;
+        assert_eq!(
+            eval(&mut glyph, "[1 2] (↑ (□) ¨) ¨").unwrap(),
+            ["[[[1]], [[1], [2]]]"]
+        );
+        assert!(matches!(
+            eval(&mut glyph, "[1 2] (↑ (□ □) ¨) ¨"),
+            Err(EvalError::NestingLimit { limit: 2, .. })
+        ));
+        assert!(matches!(
+            eval(&mut glyph, "□ □"),
+            Err(EvalError::NestingLimit { limit: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn runaway_recursion_hits_the_default_limit() {
+        // Runs on the test thread's own stack, which is smaller than the
+        // main thread's, so this would crash if a level took too much of it
+        for source in [
+            ": f f ; f",
+            "(⊕ !) ⊕ !",
+            ": g [1] (g) ¨ ; g",
+            ": h 1 (h) () ? ; h",
+            ": k 1 (k) ⍣ ; k",
+            ": c 1 (c) ⍥ ; c",
+        ] {
+            assert!(
+                matches!(
+                    error(source),
+                    EvalError::RecursionLimit {
+                        limit: DEFAULT_MAX_DEPTH,
+                        ..
                     }
-                }
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn concatenate_reports_the_axis_that_differs() {
+        assert!(matches!(
+            error(r#""ab" [2 1] ⍴ [1 2] ⋈"#),
+            EvalError::LengthMismatch {
+                left: 1,
+                right: 2,
+                ..
             }
+        ));
+        assert!(matches!(
+            error("6 ↑ [2 3] ⍴ 4 ↑ [2 2] ⍴ ⋈"),
+            EvalError::LengthMismatch {
+                left: 3,
+                right: 2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("[1 2] 8 ↑ [2 2 2] ⍴ ⋈"),
+            EvalError::DomainError { .. }
+        ));
+        assert_eq!(top("[1 2] 4 ↑ [2 2] ⍴ ⋈ △"), "[3, 2]");
+    }
+
+    #[test]
+    fn huge_shapes_are_an_error() {
+        for source in [
+            "[0 1e30 1] ↑",
+            "[0 1e300 1e-300] ↑",
+            "[-2147483648 2147483647] ↑",
+            "2147483647 ↑",
+            "[100000 100000 100000 100000] ⍳",
+            "[4096 4096] ⍳",
+            "[1] [100000 100000 100000 100000] ⍴",
+            "[1] [100000 100000] ⍴",
+        ] {
+            assert!(
+                matches!(error(source), EvalError::DomainError { .. }),
+                "{source}"
+            );
         }
-        if !current_num.is_empty() {
-            if let Ok(n) = current_num.parse() {
-                if let Some(array) = &mut current_array {
-                    array.push(n);
-                } else {
-                    ops.push(Op::Number(n));
-                }
+        assert_eq!(top("[1 2] [0 3] ⍴ △"), "[0, 3]");
+    }
+
+    #[test]
+    fn only_like_elements_are_ordered() {
+        assert_eq!(top(r#""abc" "b" <"#), "[1, 0, 0]");
+        assert_eq!(top(r#""a" 1 ="#), "[0]");
+        assert_eq!(top(r#""abc" "b" ⌈"#), r#""bbc""#);
+        assert_eq!(top("[3 1] [2.5 1] ⌊"), "[2.5, 1]");
+        for source in [
+            r#""a" 1 <"#,
+            r#"1 "a" ≥"#,
+            r#"[[1 2] "ab"] 1 >"#,
+            r#""a" 1 ⌊"#,
+            r#"1 "a" ⌈"#,
+        ] {
+            assert!(
+                matches!(error(source), EvalError::DomainError { .. }),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn big_counts_and_indices_overflow() {
+        for source in [
+            "[1 2 3] 99999999999 ⊡",
+            "[1 2 3] ¯99999999999 ⊡",
+            "99999999999 ↑",
+            "1 99999999999 (1 +) ⍣",
+            "[1 2 3] 1e20 ↙",
+            "1 99999999999 «",
+        ] {
+            assert!(
+                matches!(error(source), EvalError::Overflow { .. }),
+                "{source}"
+            );
+        }
+        let mut glyph = Glyph::new().with_overflow(OverflowPolicy::Promote);
+        assert!(matches!(
+            eval(&mut glyph, "99999999999 ↑"),
+            Err(EvalError::Overflow { .. })
+        ));
+        assert!(matches!(
+            error("[1 2 3] 2.5 ⊡"),
+            EvalError::DomainError { .. }
+        ));
+    }
+
+    #[test]
+    fn searching_looks_for_whole_rows() {
+        let matrix = "6 ↑ [3 2] ⍴";
+        assert_eq!(top(&format!("{matrix} [3 4] ⊗")), "[1]");
+        assert_eq!(top(&format!("{matrix} [9 9] ⊗")), "[3]");
+        assert_eq!(top(&format!("{matrix} [3 4 9 9] [2 2] ⍴ ⊗")), "[1, 3]");
+        assert_eq!(top(&format!("{matrix} [1 2] ⌗")), "[1]");
+        assert_eq!(top("[5 6 7] [7 5 9 6] [2 2] ⍴ ⊗"), "[[2, 0],\n [3, 1]]");
+        assert!(matches!(
+            error(&format!("{matrix} [1 2 3] ⊗")),
+            EvalError::LengthMismatch {
+                left: 2,
+                right: 3,
+                ..
             }
+        ));
+    }
+
+    #[test]
+    fn boxed_strings_sort_alphabetically() {
+        let words = r#"["banana" "apple" "fig"]"#;
+        assert_eq!(top(&format!("{words} ⇕")), r#"["apple", "banana", "fig"]"#);
+        assert_eq!(top(&format!("{words} ⍋")), "[1, 0, 2]");
+        assert_eq!(top(&format!("{words} ⍒")), "[2, 0, 1]");
+        assert_eq!(top(&format!("[1 2 3] {words} ⋔")), "[2, 1, 3]");
+        assert_eq!(top(r#"["ab" "a" "b"] ⇕"#), r#"["a", "ab", "b"]"#);
+    }
+
+    #[test]
+    fn booleans_must_be_zero_or_one() {
+        assert!(matches!(
+            error("[1 0] [1 2] ∧"),
+            EvalError::DomainError { index: Some(1), .. }
+        ));
+        // Even once the answer is known, every element is still checked
+        for source in ["[1 2] ∃", "[0 5] ∀", "[[1 1] [2 0]] ∃"] {
+            assert!(
+                matches!(error(source), EvalError::DomainError { .. }),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn index_origin_one() {
+        let mut glyph = Glyph::new().with_index_origin(IndexOrigin::One);
+        for (source, expected) in [
+            ("[3 1 2] ⍋", "[2, 3, 1]"),
+            ("[10 20 30] [3 1] ⊏", "[30, 10]"),
+            ("[2 3] ⍳ [2 3] ⊡", "[2, 3]"),
+            ("[5 6 7] [7 9] ⊗", "[3, 4]"),
+            ("10 2 [(1 +) (2 ×)] ⁇", "[20]"),
+            ("3 ↑", "[1, 2, 3]"),
+        ] {
+            glyph.stack.clear();
+            assert_eq!(eval(&mut glyph, source).unwrap(), [expected], "{source}");
         }
-        ops
+        assert!(matches!(
+            eval(&mut glyph, "[10 20 30] 0 ⊡"),
+            Err(EvalError::IndexOutOfBounds {
+                index: 0,
+                len: 3,
+                ..
+            })
+        ));
+        assert_eq!("1".parse(), Ok(IndexOrigin::One));
+        assert!("5".parse::<IndexOrigin>().is_err());
     }
 }
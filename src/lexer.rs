@@ -0,0 +1,325 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// A region of source text, tracked both as a byte range and as a
+/// human-readable (1-based) line and column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(String), // numeric literal such as 12, ¯2.5 or 1e-3, kept as written
+    String {
+        text: String,                 // contents with escapes resolved
+        terminated: bool,             // false if the input ended before the closing "
+        invalid_escape: Option<char>, // first escape other than \n, \t, \" and \\
+    },
+    OpenBracket,  // [
+    CloseBracket, // ]
+    OpenParen,    // (
+    CloseParen,   // )
+    Name(String), // word name such as evens, made of ASCII letters, digits and _
+    Glyph(char),  // any other non-whitespace character
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+pub struct Lexer<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Lexer {
+            input,
+            chars: input.char_indices().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    pub fn tokenize(mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+
+        while let Some(&(start, c)) = self.chars.peek() {
+            let (line, column) = (self.line, self.column);
+
+            let kind = match c {
+                '#' => {
+                    // Skip the rest of the line
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                    continue;
+                }
+                c if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                }
+                '"' => {
+                    self.bump();
+                    self.string()
+                }
+                '[' => {
+                    self.bump();
+                    TokenKind::OpenBracket
+                }
+                ']' => {
+                    self.bump();
+                    TokenKind::CloseBracket
+                }
+                '(' => {
+                    self.bump();
+                    TokenKind::OpenParen
+                }
+                ')' => {
+                    self.bump();
+                    TokenKind::CloseParen
+                }
+                c if c.is_ascii_digit() || (matches!(c, '-' | '¯') && self.digit_at(1)) => {
+                    self.bump();
+                    self.digits();
+                    if self.peek() == Some('.') && self.digit_at(1) {
+                        self.bump();
+                        self.digits();
+                    }
+                    // An exponent belongs to the number even without its
+                    // digits, so that `1e` is a bad number rather than `1 e`
+                    if matches!(self.peek(), Some('e' | 'E')) {
+                        self.bump();
+                        if self.sign_at(0) {
+                            self.bump();
+                        }
+                        self.digits();
+                    }
+                    TokenKind::Number(self.input[start..self.offset()].to_string())
+                }
+                c if c.is_ascii_alphabetic() || c == '_' => {
+                    while self
+                        .peek()
+                        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        self.bump();
+                    }
+                    TokenKind::Name(self.input[start..self.offset()].to_string())
+                }
+                c => {
+                    self.bump();
+                    TokenKind::Glyph(c)
+                }
+            };
+
+            tokens.push(Token {
+                kind,
+                span: Span {
+                    start,
+                    end: self.offset(),
+                    line,
+                    column,
+                },
+            });
+        }
+
+        tokens
+    }
+
+    /// Reads the rest of a string literal whose opening `"` has been consumed.
+    fn string(&mut self) -> TokenKind {
+        let mut text = String::new();
+        let mut invalid_escape = None;
+        while let Some(c) = self.bump() {
+            match c {
+                '"' => {
+                    return TokenKind::String {
+                        text,
+                        terminated: true,
+                        invalid_escape,
+                    }
+                }
+                '\\' => match self.bump() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(c @ ('"' | '\\')) => text.push(c),
+                    Some(c) => {
+                        invalid_escape.get_or_insert(c);
+                        text.push(c);
+                    }
+                    None => break,
+                },
+                c => text.push(c),
+            }
+        }
+        TokenKind::String {
+            text,
+            terminated: false,
+            invalid_escape,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    /// Looks `n` characters past the current one.
+    fn lookahead(&self, n: usize) -> Option<char> {
+        self.chars.clone().nth(n).map(|(_, c)| c)
+    }
+
+    fn digit_at(&self, n: usize) -> bool {
+        self.lookahead(n).is_some_and(|c| c.is_ascii_digit())
+    }
+
+    fn sign_at(&self, n: usize) -> bool {
+        matches!(self.lookahead(n), Some('-' | '+' | '¯'))
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.input.len(), |&(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        Lexer::new(input)
+            .tokenize()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn numbers_keep_their_text() {
+        assert_eq!(
+            kinds("12 ¯2.5 -3 1e-3 2E4"),
+            ["12", "¯2.5", "-3", "1e-3", "2E4"].map(|n| TokenKind::Number(n.to_string()))
+        );
+        assert_eq!(
+            kinds("1e 2e+ 3ex"),
+            [
+                TokenKind::Number("1e".into()),
+                TokenKind::Number("2e+".into()),
+                TokenKind::Number("3e".into()),
+                TokenKind::Name("x".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn minus_without_a_digit_is_a_glyph() {
+        assert_eq!(
+            kinds("1 - 2"),
+            [
+                TokenKind::Number("1".into()),
+                TokenKind::Glyph('-'),
+                TokenKind::Number("2".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_resolve_escapes() {
+        assert_eq!(
+            kinds(r#""a\"b\n" "open"#),
+            [
+                TokenKind::String {
+                    text: "a\"b\n".into(),
+                    terminated: true,
+                    invalid_escape: None,
+                },
+                TokenKind::String {
+                    text: "open".into(),
+                    terminated: false,
+                    invalid_escape: None,
+                },
+            ]
+        );
+        assert_eq!(
+            kinds(r#""\q\\\z""#),
+            [TokenKind::String {
+                text: "q\\z".into(),
+                terminated: true,
+                invalid_escape: Some('q'),
+            }]
+        );
+    }
+
+    #[test]
+    fn names_brackets_and_comments() {
+        assert_eq!(
+            kinds(": sq_2 (⊕ ×) ; [1] # the rest is ignored"),
+            [
+                TokenKind::Glyph(':'),
+                TokenKind::Name("sq_2".into()),
+                TokenKind::OpenParen,
+                TokenKind::Glyph('⊕'),
+                TokenKind::Glyph('×'),
+                TokenKind::CloseParen,
+                TokenKind::Glyph(';'),
+                TokenKind::OpenBracket,
+                TokenKind::Number("1".into()),
+                TokenKind::CloseBracket,
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_count_bytes_and_characters() {
+        let tokens = Lexer::new("⊕ 12\n  ab").tokenize();
+        let spans: Vec<Span> = tokens.iter().map(|token| token.span).collect();
+        assert_eq!(
+            spans,
+            [
+                Span {
+                    start: 0,
+                    end: 3,
+                    line: 1,
+                    column: 1,
+                },
+                Span {
+                    start: 4,
+                    end: 6,
+                    line: 1,
+                    column: 3,
+                },
+                Span {
+                    start: 9,
+                    end: 11,
+                    line: 2,
+                    column: 3,
+                },
+            ]
+        );
+    }
+}
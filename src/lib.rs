@@ -0,0 +1,11 @@
+pub mod error;
+pub mod glyph;
+pub mod lexer;
+pub mod number;
+pub mod operations;
+pub mod parser;
+pub mod value;
+
+pub use error::EvalError;
+pub use glyph::Glyph;
+pub use parser::ParseError;
@@ -1,14 +1,21 @@
+use std::env;
 use std::io::{self, Write};
+use std::process;
 
+use glyph::lexer::Span;
 use glyph::Glyph;
 
-mod glyph;
-mod operations;
-
 fn main() {
     let mut interpreter = Glyph::new();
     let mut input = String::new();
 
+    for arg in env::args().skip(1) {
+        if let Err(e) = configure(&mut interpreter, &arg) {
+            eprintln!("{e}");
+            process::exit(2);
+        }
+    }
+
     println!("Welcome to Glyph!");
     println!("Type 'exit' or 'quit' to exit the program.\n");
 
@@ -26,11 +33,64 @@ fn main() {
             break;
         }
 
-        let ops = Glyph::parse(&input);
-        interpreter.evaluate_sequence(ops);
+        if input.trim() == "words" {
+            for (name, body) in &interpreter.words {
+                println!(": {name} {} ;", body.source());
+            }
+            continue;
+        }
+
+        match Glyph::parse(&input) {
+            Ok(program) => {
+                if let Err(error) = interpreter.evaluate_sequence(program) {
+                    if let Some(span) = error.context().span {
+                        report(&input, &span, &error);
+                    } else {
+                        println!("Error: {error}");
+                    }
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    report(&input, &error.span, &error);
+                }
+                continue;
+            }
+        }
 
         for stack in &interpreter.stack {
-            println!("{stack:?}");
+            println!("{stack}");
         }
     }
 }
+
+/// Applies a command-line option such as `--overflow=wrapping`.
+fn configure(interpreter: &mut Glyph, arg: &str) -> Result<(), String> {
+    match arg.split_once('=') {
+        Some(("--overflow", policy)) => interpreter.overflow = policy.parse()?,
+        Some(("--division", policy)) => interpreter.division = policy.parse()?,
+        Some(("--index-origin", origin)) => interpreter.index_origin = origin.parse()?,
+        Some(("--max-depth", limit)) => interpreter.max_depth = parse_limit(limit)?,
+        Some(("--max-iterations", limit)) => interpreter.max_iterations = parse_limit(limit)?,
+        _ => return Err(format!("unknown option '{arg}'")),
+    }
+    Ok(())
+}
+
+fn parse_limit(limit: &str) -> Result<usize, String> {
+    limit
+        .parse()
+        .map_err(|_| format!("invalid limit '{limit}' (expected a non-negative integer)"))
+}
+
+/// Prints an error along with the offending source line, underlining its span.
+fn report(source: &str, span: &Span, error: &dyn std::fmt::Display) {
+    println!("Error: {error}");
+    if let Some(line) = source.lines().nth(span.line - 1) {
+        let width = source
+            .get(span.start..span.end)
+            .map_or(1, |text| text.chars().count().max(1));
+        println!("  {line}");
+        println!("  {}{}", " ".repeat(span.column - 1), "^".repeat(width));
+    }
+}
@@ -0,0 +1,619 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+
+use crate::error::EvalError;
+
+/// What arithmetic does when a result doesn't fit in an `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Checked, // raise an overflow error
+    Wrapping,   // wrap around in two's complement
+    Saturating, // clamp to i32::MIN or i32::MAX
+    Promote,    // switch to arbitrary precision
+}
+
+impl OverflowPolicy {
+    /// Turns an exact result into a number according to this policy.
+    pub fn resolve(self, exact: BigInt) -> Result<Number, EvalError> {
+        if let Some(n) = exact.to_i32() {
+            return Ok(Number::Int(n));
+        }
+        match self {
+            OverflowPolicy::Checked => Err(EvalError::overflow()),
+            OverflowPolicy::Wrapping => {
+                let modulus = BigInt::from(1u64 << 32);
+                let low = ((exact % &modulus) + &modulus) % &modulus;
+                Ok(Number::Int(low.to_u32().unwrap_or_default() as i32))
+            }
+            OverflowPolicy::Saturating => {
+                Ok(Number::Int(if exact.sign() == num_bigint::Sign::Minus {
+                    i32::MIN
+                } else {
+                    i32::MAX
+                }))
+            }
+            OverflowPolicy::Promote => Ok(Number::Big(exact)),
+        }
+    }
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "checked" => Ok(OverflowPolicy::Checked),
+            "wrapping" => Ok(OverflowPolicy::Wrapping),
+            "saturating" => Ok(OverflowPolicy::Saturating),
+            "promote" => Ok(OverflowPolicy::Promote),
+            _ => Err(format!(
+                "unknown overflow policy '{s}' (expected checked, wrapping, saturating or promote)"
+            )),
+        }
+    }
+}
+
+/// What `/` and `%` do when dividing by zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DivisionPolicy {
+    #[default]
+    Error, // raise a domain error
+    Sentinel(i32), // produce a fixed value instead
+    Float,         // produce ±infinity, or NaN for 0/0 and x%0
+}
+
+impl DivisionPolicy {
+    fn by_zero(self, dividend: &Number, modulo: bool) -> Result<Number, EvalError> {
+        match self {
+            DivisionPolicy::Error => Err(EvalError::domain_error("can't divide by zero")),
+            DivisionPolicy::Sentinel(n) => Ok(Number::Int(n)),
+            DivisionPolicy::Float if modulo || dividend.is_zero() => Ok(Number::Float(f64::NAN)),
+            DivisionPolicy::Float if dividend < &Number::Int(0) => {
+                Ok(Number::Float(f64::NEG_INFINITY))
+            }
+            DivisionPolicy::Float => Ok(Number::Float(f64::INFINITY)),
+        }
+    }
+}
+
+impl FromStr for DivisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(DivisionPolicy::Error),
+            "float" => Ok(DivisionPolicy::Float),
+            _ => s.parse().map(DivisionPolicy::Sentinel).map_err(|_| {
+                format!(
+                    "unknown division policy '{s}' (expected error, float or an integer sentinel)"
+                )
+            }),
+        }
+    }
+}
+
+/// A single array element. `Big` only ever holds values outside the `i32`
+/// range, so two equal integers always have the same representation. Numbers
+/// compare by value, so `Int(1)` equals `Float(1.0)`.
+#[derive(Debug, Clone)]
+pub enum Number {
+    Int(i32),
+    Big(BigInt),
+    Float(f64),
+}
+
+impl Number {
+    pub fn add(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        self.arith(other, policy, i32::checked_add, |a, b| a + b, |a, b| a + b)
+    }
+
+    pub fn sub(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        self.arith(other, policy, i32::checked_sub, |a, b| a - b, |a, b| a - b)
+    }
+
+    pub fn mul(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        self.arith(other, policy, i32::checked_mul, |a, b| a * b, |a, b| a * b)
+    }
+
+    /// Divides, staying an integer when the division is exact.
+    pub fn div(
+        &self,
+        other: &Number,
+        policy: OverflowPolicy,
+        division: DivisionPolicy,
+    ) -> Result<Number, EvalError> {
+        if other.is_zero() {
+            return division.by_zero(self, false);
+        }
+        let exact = match (self, other) {
+            (Number::Int(a), Number::Int(b)) => a.checked_rem(*b).unwrap_or(0) == 0,
+            (Number::Float(_), _) | (_, Number::Float(_)) => true,
+            _ => (self.to_big() % other.to_big()).is_zero(),
+        };
+        if !exact {
+            return Ok(Number::Float(self.to_f64() / other.to_f64()));
+        }
+        self.arith(other, policy, i32::checked_div, |a, b| a / b, |a, b| a / b)
+    }
+
+    pub fn rem(
+        &self,
+        other: &Number,
+        policy: OverflowPolicy,
+        division: DivisionPolicy,
+    ) -> Result<Number, EvalError> {
+        if other.is_zero() {
+            return division.by_zero(self, true);
+        }
+        self.arith(other, policy, i32::checked_rem, |a, b| a % b, |a, b| a % b)
+    }
+
+    /// Raises to a power. Negative integer powers give a float.
+    pub fn pow(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        if self.is_float() || other.is_float() || other < &Number::Int(0) {
+            return Ok(Number::Float(self.to_f64().powf(other.to_f64())));
+        }
+        let (base, exponent) = (self.to_big(), other.to_big());
+        if policy == OverflowPolicy::Wrapping {
+            return policy.resolve(base.modpow(&exponent, &BigInt::from(1u64 << 32)));
+        }
+        match base.to_i32() {
+            Some(0) if !exponent.is_zero() => return Ok(Number::Int(0)),
+            Some(1) => return Ok(Number::Int(1)),
+            Some(-1) => return Ok(Number::Int(if exponent.is_even() { 1 } else { -1 })),
+            _ => {}
+        }
+        // Any other base is at least 2^(bits - 1) in size, so past 64 bits of
+        // result only promote needs the exact value, which can take a very
+        // long time to build. The other policies just need something out of
+        // range with the right sign
+        let fits = exponent
+            .to_u64()
+            .is_some_and(|e| base.bits().saturating_sub(1).saturating_mul(e) <= 64);
+        if !fits && policy != OverflowPolicy::Promote {
+            let negative = base.is_negative() && exponent.is_odd();
+            return policy.resolve(BigInt::from(if negative { i64::MIN } else { i64::MAX }));
+        }
+        let exponent = exponent.to_u32().ok_or_else(EvalError::overflow)?;
+        policy.resolve(base.pow(exponent))
+    }
+
+    pub fn neg(&self, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        self.unary(policy, i32::checked_neg, |a| -a, |a| -a)
+    }
+
+    pub fn abs(&self, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        self.unary(policy, i32::checked_abs, |a| a.abs(), f64::abs)
+    }
+
+    /// `1`, `0` or `-1` depending on the sign.
+    pub fn sign(&self) -> Number {
+        match self {
+            Number::Float(x) if *x == 0.0 || x.is_nan() => Number::Float(*x),
+            Number::Float(x) => Number::Float(x.signum()),
+            n => Number::Int(n.to_big().signum().to_i32().unwrap_or_default()),
+        }
+    }
+
+    pub fn floor(&self, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        self.whole(policy, f64::floor)
+    }
+
+    pub fn ceil(&self, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        self.whole(policy, f64::ceil)
+    }
+
+    /// The integer square root, rounding down.
+    pub fn isqrt(&self, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        if self < &Number::Int(0) {
+            return Err(EvalError::domain_error(
+                "can't take the square root of a negative number",
+            ));
+        }
+        match self {
+            Number::Float(x) => Number::Float(x.sqrt()).floor(policy),
+            n => policy.resolve(n.to_big().sqrt()),
+        }
+    }
+
+    /// The greatest common divisor, which is never negative.
+    pub fn gcd(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        policy.resolve(self.to_integer()?.gcd(&other.to_integer()?))
+    }
+
+    /// The least common multiple, which is never negative.
+    pub fn lcm(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        policy.resolve(self.to_integer()?.lcm(&other.to_integer()?))
+    }
+
+    pub fn bit_and(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        policy.resolve(self.to_integer()? & other.to_integer()?)
+    }
+
+    pub fn bit_or(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        policy.resolve(self.to_integer()? | other.to_integer()?)
+    }
+
+    pub fn bit_xor(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        policy.resolve(self.to_integer()? ^ other.to_integer()?)
+    }
+
+    pub fn bit_not(&self, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        policy.resolve(!self.to_integer()?)
+    }
+
+    /// Shifts the bits left by `other`, or right if it is negative. Shifting
+    /// right rounds down, like dividing by a power of two.
+    pub fn shift(&self, other: &Number, policy: OverflowPolicy) -> Result<Number, EvalError> {
+        let n = self.to_integer()?;
+        let by = other.whole_i32("needs a whole-number shift")?;
+        if by < 0 {
+            return policy.resolve(n >> by.unsigned_abs());
+        }
+        // Past 64 bits every policy but promote gives the same result, so
+        // don't build a huge number just to throw it away
+        let by = match policy {
+            OverflowPolicy::Promote => by.unsigned_abs(),
+            _ => by.unsigned_abs().min(64),
+        };
+        policy.resolve(n << by)
+    }
+
+    /// The number as a boolean, which must be `0` or `1`.
+    pub fn to_bool(&self) -> Result<bool, EvalError> {
+        match self.to_i32() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => Err(EvalError::domain_error("expected a boolean (0 or 1)")),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Int(n) => *n == 0,
+            Number::Big(_) => false,
+            Number::Float(x) => *x == 0.0,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Number::Float(_))
+    }
+
+    /// The number as an `i32`, if it is a whole number in range.
+    pub fn to_i32(&self) -> Option<i32> {
+        match self {
+            Number::Int(n) => Some(*n),
+            Number::Big(_) => None,
+            Number::Float(x) if x.fract() == 0.0 => x.to_i32(),
+            Number::Float(_) => None,
+        }
+    }
+
+    /// The number as an `i32`, for glyphs that take a count, index or size.
+    /// Anything but a whole number is a domain error giving `reason`, while a
+    /// whole number too big for an `i32` overflows.
+    pub fn whole_i32(&self, reason: &str) -> Result<i32, EvalError> {
+        match self {
+            Number::Big(_) => Err(EvalError::overflow()),
+            Number::Float(x) if x.fract() == 0.0 => x.to_i32().ok_or_else(EvalError::overflow),
+            n => n.to_i32().ok_or_else(|| EvalError::domain_error(reason)),
+        }
+    }
+
+    /// Like `whole_i32`, but a negative number is a domain error too.
+    pub fn whole_usize(&self, reason: &str) -> Result<usize, EvalError> {
+        usize::try_from(self.whole_i32(reason)?).map_err(|_| EvalError::domain_error(reason))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Int(n) => f64::from(*n),
+            Number::Big(n) => n.to_f64().unwrap_or(f64::NAN),
+            Number::Float(x) => *x,
+        }
+    }
+
+    fn to_big(&self) -> BigInt {
+        match self {
+            Number::Int(n) => BigInt::from(*n),
+            Number::Big(n) => n.clone(),
+            Number::Float(x) => BigInt::from_f64(*x).unwrap_or_default(),
+        }
+    }
+
+    /// The number as an exact integer, for functions that only make sense
+    /// on whole numbers.
+    pub fn to_integer(&self) -> Result<BigInt, EvalError> {
+        match self {
+            Number::Float(x) if x.fract() != 0.0 || !x.is_finite() => {
+                Err(EvalError::domain_error("needs whole numbers"))
+            }
+            n => Ok(n.to_big()),
+        }
+    }
+
+    /// Rounds a float to a whole number with `round`, following the overflow
+    /// policy if it doesn't fit. Infinity and NaN are left as they are.
+    fn whole(&self, policy: OverflowPolicy, round: fn(f64) -> f64) -> Result<Number, EvalError> {
+        match self {
+            Number::Float(x) if x.is_finite() => policy.resolve(Number::Float(round(*x)).to_big()),
+            n => Ok(n.clone()),
+        }
+    }
+
+    /// The one-operand counterpart of `arith`.
+    fn unary(
+        &self,
+        policy: OverflowPolicy,
+        fast: fn(i32) -> Option<i32>,
+        exact: fn(BigInt) -> BigInt,
+        float: fn(f64) -> f64,
+    ) -> Result<Number, EvalError> {
+        match self {
+            Number::Int(a) => match fast(*a) {
+                Some(n) => Ok(Number::Int(n)),
+                None => policy.resolve(exact(BigInt::from(*a))),
+            },
+            Number::Big(a) => policy.resolve(exact(a.clone())),
+            Number::Float(x) => Ok(Number::Float(float(*x))),
+        }
+    }
+
+    /// Runs `fast` on plain integers, falling back to the exact result and the
+    /// overflow policy when it doesn't fit. Floats promote the whole operation
+    /// to `float`.
+    fn arith(
+        &self,
+        other: &Number,
+        policy: OverflowPolicy,
+        fast: fn(i32, i32) -> Option<i32>,
+        exact: fn(BigInt, BigInt) -> BigInt,
+        float: fn(f64, f64) -> f64,
+    ) -> Result<Number, EvalError> {
+        if let (Number::Int(a), Number::Int(b)) = (self, other) {
+            if let Some(n) = fast(*a, *b) {
+                return Ok(Number::Int(n));
+            }
+        }
+        if self.is_float() || other.is_float() {
+            return Ok(Number::Float(float(self.to_f64(), other.to_f64())));
+        }
+        policy.resolve(exact(self.to_big(), other.to_big()))
+    }
+}
+
+impl From<i32> for Number {
+    fn from(n: i32) -> Self {
+        Number::Int(n)
+    }
+}
+
+impl From<bool> for Number {
+    fn from(b: bool) -> Self {
+        Number::Int(i32::from(b))
+    }
+}
+
+/// Keeps `Big` for values that don't fit in an `i32`.
+impl From<BigInt> for Number {
+    fn from(n: BigInt) -> Self {
+        match n.to_i32() {
+            Some(n) => Number::Int(n),
+            None => Number::Big(n),
+        }
+    }
+}
+
+impl From<f64> for Number {
+    fn from(x: f64) -> Self {
+        Number::Float(x)
+    }
+}
+
+/// Orders floats numerically, with NaN above everything else (and equal to
+/// itself) so that sorting is total.
+fn cmp_f64(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => a.cmp(b),
+            (Number::Float(_), _) | (_, Number::Float(_)) => cmp_f64(self.to_f64(), other.to_f64()),
+            _ => self.to_big().cmp(&other.to_big()),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Int(n) => write!(f, "{n}"),
+            Number::Big(n) => write!(f, "{n}"),
+            Number::Float(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: [OverflowPolicy; 4] = [
+        OverflowPolicy::Checked,
+        OverflowPolicy::Wrapping,
+        OverflowPolicy::Saturating,
+        OverflowPolicy::Promote,
+    ];
+
+    fn big(n: i64) -> Number {
+        Number::Big(BigInt::from(n))
+    }
+
+    #[test]
+    fn overflow_follows_the_policy() {
+        let max = Number::Int(i32::MAX);
+        let one = Number::Int(1);
+        assert!(max.add(&one, OverflowPolicy::Checked).is_err());
+        assert_eq!(
+            max.add(&one, OverflowPolicy::Wrapping).unwrap(),
+            Number::Int(i32::MIN)
+        );
+        assert_eq!(
+            max.add(&one, OverflowPolicy::Saturating).unwrap(),
+            Number::Int(i32::MAX)
+        );
+        assert_eq!(
+            max.add(&one, OverflowPolicy::Promote).unwrap(),
+            big(1 << 31)
+        );
+    }
+
+    #[test]
+    fn results_back_in_range_are_plain_integers() {
+        let result = big(1 << 31).sub(&Number::Int(1), OverflowPolicy::Promote);
+        assert!(matches!(result, Ok(Number::Int(i32::MAX))));
+    }
+
+    #[test]
+    fn division_stays_exact_when_it_can() {
+        let (policy, division) = (OverflowPolicy::Checked, DivisionPolicy::Error);
+        let div = |a: i32, b: i32| Number::Int(a).div(&Number::Int(b), policy, division);
+        assert!(matches!(div(6, 2), Ok(Number::Int(3))));
+        assert!(matches!(div(7, 2), Ok(Number::Float(x)) if x == 3.5));
+        assert!(div(1, 0).is_err());
+        assert!(div(i32::MIN, -1).is_err());
+    }
+
+    #[test]
+    fn division_by_zero_follows_the_policy() {
+        let zero = Number::Int(0);
+        let policy = OverflowPolicy::Checked;
+        assert_eq!(
+            Number::Int(5)
+                .div(&zero, policy, DivisionPolicy::Sentinel(-1))
+                .unwrap(),
+            Number::Int(-1)
+        );
+        let inf = Number::Int(-5)
+            .div(&zero, policy, DivisionPolicy::Float)
+            .unwrap();
+        assert_eq!(inf.to_f64(), f64::NEG_INFINITY);
+        let nan = Number::Int(5)
+            .rem(&zero, policy, DivisionPolicy::Float)
+            .unwrap();
+        assert!(nan.to_f64().is_nan());
+    }
+
+    #[test]
+    fn power() {
+        let pow = |a: i32, b: i32, policy| Number::Int(a).pow(&Number::Int(b), policy);
+        for policy in POLICIES {
+            assert_eq!(pow(2, 10, policy).unwrap(), Number::Int(1024));
+            assert_eq!(pow(-1, 7, policy).unwrap(), Number::Int(-1));
+        }
+        assert_eq!(
+            pow(2, -1, OverflowPolicy::Checked).unwrap(),
+            Number::Float(0.5)
+        );
+        assert_eq!(pow(0, 0, OverflowPolicy::Checked).unwrap(), Number::Int(1));
+        assert_eq!(
+            pow(3, 5, OverflowPolicy::Wrapping).unwrap(),
+            Number::Int(243)
+        );
+        assert_eq!(
+            pow(-2, 31, OverflowPolicy::Checked).unwrap(),
+            Number::Int(i32::MIN)
+        );
+        assert!(pow(2, 31, OverflowPolicy::Checked).is_err());
+        assert_eq!(pow(2, 31, OverflowPolicy::Promote).unwrap(), big(1 << 31));
+    }
+
+    #[test]
+    fn huge_powers_are_decided_without_computing_them() {
+        let huge = Number::Int(2_000_000_000);
+        let pow = |a: i32, policy| Number::Int(a).pow(&huge, policy);
+        assert!(pow(3, OverflowPolicy::Checked).is_err());
+        assert_eq!(
+            pow(3, OverflowPolicy::Saturating).unwrap(),
+            Number::Int(i32::MAX)
+        );
+        assert_eq!(
+            Number::Int(-3)
+                .pow(&Number::Int(2_000_000_001), OverflowPolicy::Saturating)
+                .unwrap(),
+            Number::Int(i32::MIN)
+        );
+        assert_eq!(pow(2, OverflowPolicy::Wrapping).unwrap(), Number::Int(0));
+        // Any odd number to the power of 2^30 is 1 modulo 2^32
+        assert_eq!(
+            Number::Int(3)
+                .pow(&Number::Int(1 << 30), OverflowPolicy::Wrapping)
+                .unwrap(),
+            Number::Int(1)
+        );
+        // Near the edge, the result is still worked out exactly
+        assert_eq!(
+            Number::Int(3)
+                .pow(&Number::Int(20), OverflowPolicy::Saturating)
+                .unwrap(),
+            Number::Int(i32::MAX)
+        );
+    }
+
+    #[test]
+    fn booleans_must_be_zero_or_one() {
+        assert!(!Number::Int(0).to_bool().unwrap());
+        assert!(Number::Float(1.0).to_bool().unwrap());
+        assert!(Number::Int(2).to_bool().is_err());
+        assert!(Number::Float(0.5).to_bool().is_err());
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(Number::Int(1), Number::Float(1.0));
+        assert!(Number::Int(i32::MAX) < big(1 << 31));
+        assert!(big(-(1 << 40)) < Number::Float(-1e9));
+        assert!(Number::Float(f64::NAN) > Number::Float(f64::INFINITY));
+    }
+
+    #[test]
+    fn shifts_round_down() {
+        let policy = OverflowPolicy::Checked;
+        let shift = |a: i32, b: i32| Number::Int(a).shift(&Number::Int(b), policy).unwrap();
+        assert_eq!(shift(1, 4), Number::Int(16));
+        assert_eq!(shift(-16, -2), Number::Int(-4));
+        assert_eq!(shift(-1, -40), Number::Int(-1));
+        assert!(Number::Int(1).shift(&Number::Int(1000), policy).is_err());
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!("promote".parse(), Ok(OverflowPolicy::Promote));
+        assert_eq!("-1".parse(), Ok(DivisionPolicy::Sentinel(-1)));
+        assert!("loose".parse::<OverflowPolicy>().is_err());
+    }
+}
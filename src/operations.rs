@@ -1,31 +1,438 @@
-#[derive(Debug)]
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::lexer::Span;
+use crate::number::Number;
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Op {
-    Number(i32),     // numeric literal
-    Array(Vec<i32>), // Array literal [1 2 3]
-    Range,           // ↑ creates range [1..=n]
-    Add,             // + adds arrays elementwise
-    Subtract,        // - subtracts arrays elementwise
-    Multiply,        // × multiplies arrays elementwise
-    Divide,          // / divides arrays elementwise
-    Modulo,          // % calculates the remainder of arrays elementwise
-    Sum,             // Σ sums all elements
-    Product,         // Π multiplies all elements
-    Reverse,         // ↔ reverses array elements
-    Sort,            // ⇕ sorts array elements
-    Length,          // ⋕ pushes array length
-    Greater,         // > compares arrays elementwise
-    GreaterEqual,    // ≥ compares arrays elementwise
-    Less,            // < compares arrays elementwise
-    LessEqual,       // ≤ compares arrays elementwise
-    Equal,           // = compares arrays elementwise
-    Clear,           // ∅ clears the stack
-    Filter,          // ⊃ filters array using condition
-    Not,             // ¬ inverts boolean array (0->1, 1->0)
-    Duplicate,       // ⊕ duplicates top stack item
-    Concatenate,     // ⋈ joins two arrays together
-    Split,           // ⋉ splits array at index
-    PopLeft,         // ⊣ removes first element
-    PopRight,        // ⊢ removes last element
-    AppendLeft,      // ⊲ adds element to start
-    AppendRight,     // ⊳ adds element to end
+    Number(Number),            // numeric literal
+    Array(Value),              // Array literal [1 2 3], possibly nested [[1 2] [3]]
+    Quotation(Quotation),      // Quotation literal (2 ×), pushed without running it
+    Define(String, Quotation), // Definition : name ... ; of a word
+    Name(String),              // pushes the binding, or runs the word, with this name
+    Assign(String),            // →name pops the top value into a binding
+    Range,                     // ↑ creates a range of n numbers, or [start stop step]
+    Indices,                   // ⍳ creates the indices of every element of a shape
+    Add,                       // + adds arrays elementwise
+    Subtract,                  // - subtracts arrays elementwise
+    Multiply,                  // × multiplies arrays elementwise
+    Divide,                    // / divides arrays elementwise
+    Modulo,                    // % calculates the remainder of arrays elementwise
+    Power,                     // * raises arrays to a power elementwise
+    Minimum,                   // ⌊ takes the smaller of arrays elementwise
+    Maximum,                   // ⌈ takes the larger of arrays elementwise
+    Gcd,                       // ⊓ greatest common divisor of arrays elementwise
+    Lcm,                       // ⊔ least common multiple of arrays elementwise
+    Negate,                    // ¯ negates every element
+    Abs,                       // ⌵ takes the absolute value of every element
+    Sign,                      // ± takes the sign of every element
+    Floor,                     // ⌋ rounds every element down
+    Ceiling,                   // ⌉ rounds every element up
+    Sqrt,                      // √ takes the integer square root of every element
+    Sum,                       // Σ sums all elements
+    Product,                   // Π multiplies all elements
+    Reverse,                   // ↔ reverses array elements
+    Sort,                      // ⇕ sorts array elements
+    SortDown,                  // ⇵ sorts array elements in descending order
+    GradeUp,                   // ⍋ pushes the indices that would sort an array
+    GradeDown,                 // ⍒ pushes the indices that would sort an array descending
+    SortBy,                    // ⋔ sorts an array by the rows of another
+    Length,                    // ⋕ pushes array length
+    Greater,                   // > compares arrays elementwise
+    GreaterEqual,              // ≥ compares arrays elementwise
+    Less,                      // < compares arrays elementwise
+    LessEqual,                 // ≤ compares arrays elementwise
+    Equal,                     // = compares arrays elementwise
+    Unique,                    // ◴ removes duplicate rows
+    Sieve,                     // ◰ marks the first occurrence of each row
+    Member,                    // ∊ checks which elements occur in another array
+    IndexOf,                   // ⊗ finds where elements first occur in another array
+    Occurrences,               // ⌗ counts how often elements occur in another array
+    Union,                     // ∪ rows in either array
+    Intersection,              // ∩ rows in both arrays
+    Difference,                // ∖ rows in the first array but not the second
+    Clear,                     // ∅ clears the stack
+    Filter,                    // ⊃ filters array using condition
+    Not,                       // ¬ inverts boolean array (0->1, 1->0)
+    And,                       // ∧ ands boolean arrays elementwise
+    Or,                        // ∨ ors boolean arrays elementwise
+    Xor,                       // ⊻ xors boolean arrays elementwise
+    Nand,                      // ⊼ nands boolean arrays elementwise
+    BitAnd,                    // & ands the bits of integers elementwise
+    BitOr,                     // | ors the bits of integers elementwise
+    BitXor,                    // ^ xors the bits of integers elementwise
+    BitNot,                    // ~ flips the bits of every integer
+    ShiftLeft,                 // « shifts the bits of integers left elementwise
+    ShiftRight,                // » shifts the bits of integers right elementwise
+    Encode,                    // ⊤ converts numbers to digits in a base
+    Decode,                    // ⊥ converts digits in a base to numbers
+    Any,                       // ∃ checks whether any element is 1
+    All,                       // ∀ checks whether every element is 1
+    Duplicate,                 // ⊕ duplicates top stack item
+    Swap,                      // ⇄ swaps the top two stack items
+    Drop,                      // ◌ discards the top stack item
+    Over,                      // ⇈ copies the second stack item to the top
+    Rotate,                    // ⟲ moves the third stack item to the top
+    Nip,                       // ⊝ discards the second stack item
+    Pick,                      // ⇑ copies the stack item n below the top
+    Dip,                       // ⊙ runs a quotation under the top stack item
+    Concatenate,               // ⋈ joins two arrays together
+    Split,                     // ⋉ splits array at index
+    RotateRows,                // ⌽ rotates rows left by n (right if negative)
+    Windows,                   // ◫ makes every run of n consecutive rows
+    Chunks,                    // ⧉ splits an array into chunks of n rows
+    Partition,                 // ⊜ groups runs of rows with the same non-zero key
+    Index,                     // ⊡ picks the row at an index
+    Select,                    // ⊏ selects the rows at many indices
+    Take,                      // ↙ takes the first n rows (last n if negative)
+    Skip,                      // ↘ drops the first n rows (last n if negative)
+    PopLeft,                   // ⊣ removes first element
+    PopRight,                  // ⊢ removes last element
+    AppendLeft,                // ⊲ adds element to start
+    AppendRight,               // ⊳ adds element to end
+    Box,                       // □ wraps an array into a single element
+    Unbox,                     // ◇ pushes the contents of each element
+    Shape,                     // △ pushes the shape of an array
+    Reshape,                   // ⍴ rearranges an array into a new shape
+    Ravel,                     // ♭ flattens an array into a list
+    Transpose,                 // ⍉ reverses the axes of an array
+    CodePoints,                // ⌂ converts characters to code points
+    Characters,                // ⌑ converts code points to characters
+    Apply,                     // ! runs a quotation
+    Each,                      // ¨ runs a quotation on every row
+    Reduce,                    // ⌿ folds rows together with a quotation
+    Scan,                      // ⍀ folds rows together, keeping every step
+    If,                        // ? runs one of two quotations depending on a condition
+    Switch,                    // ⁇ runs the quotation at an index in a list of quotations
+    Repeat,                    // ⍣ runs a quotation n times
+    DoWhile,                   // ⍢ runs a quotation until it leaves a false condition
+    Converge,                  // ⍥ runs a quotation until its result stops changing
+}
+
+/// Every single-character glyph and the op it stands for.
+const GLYPHS: &[(char, Op)] = &[
+    ('↑', Op::Range),
+    ('⍳', Op::Indices),
+    ('+', Op::Add),
+    ('-', Op::Subtract),
+    ('×', Op::Multiply),
+    ('/', Op::Divide),
+    ('%', Op::Modulo),
+    ('*', Op::Power),
+    ('⌊', Op::Minimum),
+    ('⌈', Op::Maximum),
+    ('⊓', Op::Gcd),
+    ('⊔', Op::Lcm),
+    ('¯', Op::Negate),
+    ('⌵', Op::Abs),
+    ('±', Op::Sign),
+    ('⌋', Op::Floor),
+    ('⌉', Op::Ceiling),
+    ('√', Op::Sqrt),
+    ('∑', Op::Sum),
+    ('∏', Op::Product),
+    ('↔', Op::Reverse),
+    ('⇕', Op::Sort),
+    ('⇵', Op::SortDown),
+    ('⍋', Op::GradeUp),
+    ('⍒', Op::GradeDown),
+    ('⋔', Op::SortBy),
+    ('⋕', Op::Length),
+    ('>', Op::Greater),
+    ('≥', Op::GreaterEqual),
+    ('<', Op::Less),
+    ('≤', Op::LessEqual),
+    ('=', Op::Equal),
+    ('◴', Op::Unique),
+    ('◰', Op::Sieve),
+    ('∊', Op::Member),
+    ('⊗', Op::IndexOf),
+    ('⌗', Op::Occurrences),
+    ('∪', Op::Union),
+    ('∩', Op::Intersection),
+    ('∖', Op::Difference),
+    ('∅', Op::Clear),
+    ('⊃', Op::Filter),
+    ('¬', Op::Not),
+    ('∧', Op::And),
+    ('∨', Op::Or),
+    ('⊻', Op::Xor),
+    ('⊼', Op::Nand),
+    ('&', Op::BitAnd),
+    ('|', Op::BitOr),
+    ('^', Op::BitXor),
+    ('~', Op::BitNot),
+    ('«', Op::ShiftLeft),
+    ('»', Op::ShiftRight),
+    ('⊤', Op::Encode),
+    ('⊥', Op::Decode),
+    ('∃', Op::Any),
+    ('∀', Op::All),
+    ('⊕', Op::Duplicate),
+    ('⇄', Op::Swap),
+    ('◌', Op::Drop),
+    ('⇈', Op::Over),
+    ('⟲', Op::Rotate),
+    ('⊝', Op::Nip),
+    ('⇑', Op::Pick),
+    ('⊙', Op::Dip),
+    ('⋈', Op::Concatenate),
+    ('⋉', Op::Split),
+    ('⌽', Op::RotateRows),
+    ('◫', Op::Windows),
+    ('⧉', Op::Chunks),
+    ('⊜', Op::Partition),
+    ('⊡', Op::Index),
+    ('⊏', Op::Select),
+    ('↙', Op::Take),
+    ('↘', Op::Skip),
+    ('⊣', Op::PopLeft),
+    ('⊢', Op::PopRight),
+    ('⊲', Op::AppendLeft),
+    ('⊳', Op::AppendRight),
+    ('□', Op::Box),
+    ('◇', Op::Unbox),
+    ('△', Op::Shape),
+    ('⍴', Op::Reshape),
+    ('♭', Op::Ravel),
+    ('⍉', Op::Transpose),
+    ('⌂', Op::CodePoints),
+    ('⌑', Op::Characters),
+    ('!', Op::Apply),
+    ('¨', Op::Each),
+    ('⌿', Op::Reduce),
+    ('⍀', Op::Scan),
+    ('?', Op::If),
+    ('⁇', Op::Switch),
+    ('⍣', Op::Repeat),
+    ('⍢', Op::DoWhile),
+    ('⍥', Op::Converge),
+];
+
+impl Op {
+    /// Looks up the op for a single-character glyph.
+    pub fn from_glyph(glyph: char) -> Option<Op> {
+        GLYPHS
+            .iter()
+            .find(|(c, _)| *c == glyph)
+            .map(|(_, op)| op.clone())
+    }
+
+    /// The glyph this op is written as, if it isn't a literal.
+    pub fn glyph(&self) -> Option<char> {
+        GLYPHS.iter().find(|(_, op)| op == self).map(|(c, _)| *c)
+    }
+
+    /// How many values this op takes off the stack.
+    pub fn arity(&self) -> usize {
+        match self {
+            Op::Number(_)
+            | Op::Array(_)
+            | Op::Quotation(_)
+            | Op::Define(..)
+            | Op::Name(_)
+            | Op::Clear => 0,
+            Op::Range
+            | Op::Indices
+            | Op::Sum
+            | Op::Negate
+            | Op::Abs
+            | Op::Sign
+            | Op::Floor
+            | Op::Ceiling
+            | Op::Sqrt
+            | Op::Product
+            | Op::Reverse
+            | Op::Sort
+            | Op::Unique
+            | Op::Sieve
+            | Op::SortDown
+            | Op::GradeUp
+            | Op::GradeDown
+            | Op::Length
+            | Op::Not
+            | Op::Any
+            | Op::All
+            | Op::BitNot
+            | Op::Duplicate
+            | Op::Drop
+            | Op::Pick
+            | Op::PopLeft
+            | Op::PopRight
+            | Op::Box
+            | Op::Unbox
+            | Op::Shape
+            | Op::Ravel
+            | Op::Transpose
+            | Op::CodePoints
+            | Op::Characters
+            | Op::Apply
+            | Op::DoWhile
+            | Op::Assign(_) => 1,
+            Op::Add
+            | Op::Subtract
+            | Op::Multiply
+            | Op::Divide
+            | Op::Modulo
+            | Op::Power
+            | Op::Minimum
+            | Op::Maximum
+            | Op::Gcd
+            | Op::Lcm
+            | Op::Greater
+            | Op::GreaterEqual
+            | Op::Less
+            | Op::LessEqual
+            | Op::Equal
+            | Op::And
+            | Op::Or
+            | Op::Xor
+            | Op::Nand
+            | Op::BitAnd
+            | Op::BitOr
+            | Op::BitXor
+            | Op::ShiftLeft
+            | Op::ShiftRight
+            | Op::Encode
+            | Op::Decode
+            | Op::Member
+            | Op::IndexOf
+            | Op::Occurrences
+            | Op::Union
+            | Op::Intersection
+            | Op::Difference
+            | Op::Filter
+            | Op::Concatenate
+            | Op::Split
+            | Op::RotateRows
+            | Op::Windows
+            | Op::Chunks
+            | Op::Partition
+            | Op::SortBy
+            | Op::Index
+            | Op::Select
+            | Op::Take
+            | Op::Skip
+            | Op::AppendLeft
+            | Op::AppendRight
+            | Op::Reshape
+            | Op::Each
+            | Op::Reduce
+            | Op::Scan
+            | Op::Switch
+            | Op::Swap
+            | Op::Over
+            | Op::Nip
+            | Op::Dip
+            | Op::Repeat
+            | Op::Converge => 2,
+            Op::If | Op::Rotate => 3,
+        }
+    }
+
+    /// Whether this op runs quotations, which can reach further down the
+    /// stack than the op's own arity.
+    pub fn runs_code(&self) -> bool {
+        matches!(
+            self,
+            Op::Name(_)
+                | Op::Apply
+                | Op::Each
+                | Op::Reduce
+                | Op::Scan
+                | Op::If
+                | Op::Switch
+                | Op::Repeat
+                | Op::DoWhile
+                | Op::Converge
+                | Op::Dip
+        )
+    }
+
+    /// Whether this op takes a function, so that a glyph written right
+    /// before it (`+⌿`) is passed as a quotation (`(+) ⌿`).
+    pub fn takes_function(&self) -> bool {
+        matches!(self, Op::Each | Op::Reduce | Op::Scan)
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Number(n) => write!(f, "{n}"),
+            Op::Array(value) => write!(f, "{value}"),
+            Op::Quotation(quotation) => write!(f, "{quotation}"),
+            Op::Define(name, body) => write!(f, ": {name} {} ;", body.source()),
+            Op::Name(name) => write!(f, "{name}"),
+            Op::Assign(name) => write!(f, "→{name}"),
+            op => match op.glyph() {
+                Some(c) => write!(f, "{c}"),
+                None => write!(f, "{op:?}"),
+            },
+        }
+    }
+}
+
+/// An op together with the source span it was parsed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub op: Op,
+    pub span: Span,
+}
+
+pub type Program = Vec<Instruction>;
+
+/// A block of code such as `(2 ×)`, which sits on the stack like any other
+/// value until it is run. Quotations compare by their ops alone, ignoring
+/// where they were written.
+#[derive(Debug, Clone)]
+pub struct Quotation(Rc<Program>);
+
+impl Quotation {
+    pub fn new(program: Program) -> Self {
+        Quotation(Rc::new(program))
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.0
+    }
+
+    /// The quotation's ops written out, without the parentheses.
+    pub fn source(&self) -> String {
+        let ops: Vec<String> = self.ops().map(Op::to_string).collect();
+        ops.join(" ")
+    }
+
+    fn ops(&self) -> impl Iterator<Item = &Op> {
+        self.0.iter().map(|instruction| &instruction.op)
+    }
+}
+
+impl Ord for Quotation {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.ops().cmp(other.ops())
+    }
+}
+
+impl PartialOrd for Quotation {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Quotation {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Quotation {}
+
+impl fmt::Display for Quotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({})", self.source())
+    }
 }
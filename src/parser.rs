@@ -0,0 +1,454 @@
+use std::fmt;
+use std::iter::Peekable;
+
+use num_bigint::BigInt;
+
+use crate::lexer::{Lexer, Span, Token, TokenKind};
+use crate::number::Number;
+use crate::operations::{Instruction, Op, Program, Quotation};
+use crate::value::{Element, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnknownGlyph(char),     // character that isn't a glyph
+    InvalidNumber(String),  // literal that doesn't fit in a number
+    UnterminatedArray,      // [ without a matching ]
+    UnterminatedString,     // " without a matching "
+    InvalidEscape(char),    // backslash in a string followed by this
+    UnmatchedCloseBracket,  // ] without a matching [
+    UnterminatedQuotation,  // ( without a matching )
+    UnmatchedCloseParen,    // ) without a matching (
+    ExpectedName(char),     // : or → not followed by a name
+    UnterminatedDefinition, // : without a matching ;
+    UnmatchedSemicolon,     // ; without a matching :
+    NameInArray(String),    // word used inside an array literal
+    GlyphInArray(char),     // glyph used inside an array literal
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.span.line, self.span.column)?;
+        match &self.kind {
+            ParseErrorKind::UnknownGlyph(c) => write!(f, "unknown glyph '{c}'"),
+            ParseErrorKind::InvalidNumber(n) => write!(f, "invalid number '{n}'"),
+            ParseErrorKind::UnterminatedArray => write!(f, "unterminated array, expected ']'"),
+            ParseErrorKind::UnterminatedString => {
+                write!(f, "unterminated string, expected '\"'")
+            }
+            ParseErrorKind::InvalidEscape(c) => write!(f, "invalid escape '\\{c}' in string"),
+            ParseErrorKind::UnmatchedCloseBracket => write!(f, "unmatched ']'"),
+            ParseErrorKind::UnterminatedQuotation => {
+                write!(f, "unterminated quotation, expected ')'")
+            }
+            ParseErrorKind::UnmatchedCloseParen => write!(f, "unmatched ')'"),
+            ParseErrorKind::ExpectedName(c) => write!(f, "expected a name after '{c}'"),
+            ParseErrorKind::UnterminatedDefinition => {
+                write!(f, "unterminated definition, expected ';'")
+            }
+            ParseErrorKind::UnmatchedSemicolon => write!(f, "unmatched ';'"),
+            ParseErrorKind::NameInArray(name) => {
+                write!(f, "word '{name}' is not allowed inside an array literal")
+            }
+            ParseErrorKind::GlyphInArray(c) => {
+                write!(f, "glyph '{c}' is not allowed inside an array literal")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(input: &str) -> Result<Program, Vec<ParseError>> {
+    Parser::new(Lexer::new(input).tokenize()).parse()
+}
+
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+    errors: Vec<ParseError>,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Parser {
+            tokens: tokens.into_iter().peekable(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn parse(mut self) -> Result<Program, Vec<ParseError>> {
+        let (program, _) = self.program(None);
+        if self.errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    /// Parses instructions until the input runs out or, inside a quotation
+    /// opened at `open`, until the matching `)`, whose span is returned.
+    fn program(&mut self, open: Option<Span>) -> (Program, Option<Span>) {
+        let mut program = Vec::new();
+
+        while let Some(token) = self.tokens.next() {
+            let op = match token.kind {
+                TokenKind::Number(text) => self
+                    .number(&text, token.span)
+                    .map(|n| (Op::Number(n), token.span)),
+                TokenKind::OpenBracket => self.array(token.span).map(|(value, span)| {
+                    let op = match value.data.as_slice() {
+                        [Element::Num(n)] => Op::Number(n.clone()),
+                        _ => Op::Array(value),
+                    };
+                    (op, span)
+                }),
+                TokenKind::String {
+                    text,
+                    terminated,
+                    invalid_escape,
+                } => self
+                    .string(text, terminated, invalid_escape, token.span)
+                    .map(|value| (Op::Array(value), token.span)),
+                TokenKind::CloseBracket => {
+                    self.error(ParseErrorKind::UnmatchedCloseBracket, token.span);
+                    None
+                }
+                TokenKind::OpenParen => self
+                    .quotation(token.span)
+                    .map(|(quotation, span)| (Op::Quotation(quotation), span)),
+                TokenKind::CloseParen if open.is_some() => return (program, Some(token.span)),
+                TokenKind::CloseParen => {
+                    self.error(ParseErrorKind::UnmatchedCloseParen, token.span);
+                    None
+                }
+                TokenKind::Glyph(':') => self.definition(token.span),
+                TokenKind::Glyph(';') => {
+                    self.error(ParseErrorKind::UnmatchedSemicolon, token.span);
+                    None
+                }
+                TokenKind::Glyph('→') => self.name('→', token.span).map(|(name, name_span)| {
+                    let span = Span {
+                        end: name_span.end,
+                        ..token.span
+                    };
+                    (Op::Assign(name), span)
+                }),
+                TokenKind::Name(name) => {
+                    Some((self.function(Op::Name(name), token.span), token.span))
+                }
+                TokenKind::Glyph(c) => {
+                    let op = Op::from_glyph(c);
+                    if op.is_none() {
+                        self.error(ParseErrorKind::UnknownGlyph(c), token.span);
+                    }
+                    op.map(|op| (self.function(op, token.span), token.span))
+                }
+            };
+
+            if let Some((op, span)) = op {
+                program.push(Instruction { op, span });
+            }
+        }
+
+        if let Some(open) = open {
+            self.error(ParseErrorKind::UnterminatedQuotation, open);
+        }
+        (program, None)
+    }
+
+    /// Turns a glyph written directly before a glyph that takes a function,
+    /// as in `+⌿`, into a quotation of its own.
+    fn function(&mut self, op: Op, span: Span) -> Op {
+        let modifier = self.tokens.peek().and_then(|next| match next.kind {
+            TokenKind::Glyph(c) if next.span.start == span.end => Op::from_glyph(c),
+            _ => None,
+        });
+        if modifier.is_some_and(|modifier| modifier.takes_function()) {
+            Op::Quotation(Quotation::new(vec![Instruction { op, span }]))
+        } else {
+            op
+        }
+    }
+
+    /// Parses the name that must follow the glyph `c`.
+    fn name(&mut self, c: char, span: Span) -> Option<(String, Span)> {
+        match self
+            .tokens
+            .next_if(|token| matches!(token.kind, TokenKind::Name(_)))
+        {
+            Some(Token {
+                kind: TokenKind::Name(name),
+                span,
+            }) => Some((name, span)),
+            _ => {
+                self.error(ParseErrorKind::ExpectedName(c), span);
+                None
+            }
+        }
+    }
+
+    /// Parses the rest of a definition such as `: evens ⊕ 2 % ¬ ⊃ ;` whose
+    /// `:` has already been consumed.
+    fn definition(&mut self, colon: Span) -> Option<(Op, Span)> {
+        let (name, _) = self.name(':', colon)?;
+
+        let mut body = Vec::new();
+        for token in self.tokens.by_ref() {
+            if token.kind == TokenKind::Glyph(';') {
+                let mut parser = Parser::new(body);
+                let (program, _) = parser.program(None);
+                self.errors.extend(parser.errors);
+                let span = Span {
+                    end: token.span.end,
+                    ..colon
+                };
+                return Some((Op::Define(name, Quotation::new(program)), span));
+            }
+            body.push(token);
+        }
+
+        self.error(ParseErrorKind::UnterminatedDefinition, colon);
+        None
+    }
+
+    /// Parses the rest of a quotation whose `(` has already been consumed.
+    fn quotation(&mut self, open: Span) -> Option<(Quotation, Span)> {
+        let (program, close) = self.program(Some(open));
+        let span = Span {
+            end: close?.end,
+            ..open
+        };
+        Some((Quotation::new(program), span))
+    }
+
+    /// Parses the rest of an array literal whose `[` has already been consumed.
+    /// Nested literals become boxed elements, and quotations are kept as is.
+    fn array(&mut self, open: Span) -> Option<(Value, Span)> {
+        let mut data = Vec::new();
+        let mut valid = true;
+
+        while let Some(token) = self.tokens.next() {
+            match token.kind {
+                TokenKind::Number(text) => match self.number(&text, token.span) {
+                    Some(n) => data.push(Element::Num(n)),
+                    None => valid = false,
+                },
+                TokenKind::CloseBracket => {
+                    let span = Span {
+                        end: token.span.end,
+                        ..open
+                    };
+                    return valid.then_some((Value::new(data), span));
+                }
+                TokenKind::String {
+                    text,
+                    terminated,
+                    invalid_escape,
+                } => match self.string(text, terminated, invalid_escape, token.span) {
+                    Some(value) => data.push(Element::Boxed(value)),
+                    None => valid = false,
+                },
+                TokenKind::OpenBracket => match self.array(token.span) {
+                    Some((inner, _)) => data.push(Element::Boxed(inner)),
+                    None => valid = false,
+                },
+                TokenKind::OpenParen => match self.quotation(token.span) {
+                    Some((quotation, _)) => data.push(Element::Quotation(quotation)),
+                    None => valid = false,
+                },
+                TokenKind::CloseParen => {
+                    self.error(ParseErrorKind::UnmatchedCloseParen, token.span);
+                    valid = false;
+                }
+                TokenKind::Name(name) => {
+                    self.error(ParseErrorKind::NameInArray(name), token.span);
+                    valid = false;
+                }
+                TokenKind::Glyph(c) => {
+                    self.error(ParseErrorKind::GlyphInArray(c), token.span);
+                    valid = false;
+                }
+            }
+        }
+
+        self.error(ParseErrorKind::UnterminatedArray, open);
+        None
+    }
+
+    fn string(
+        &mut self,
+        text: String,
+        terminated: bool,
+        invalid_escape: Option<char>,
+        span: Span,
+    ) -> Option<Value> {
+        if !terminated {
+            self.error(ParseErrorKind::UnterminatedString, span);
+            return None;
+        }
+        if let Some(c) = invalid_escape {
+            self.error(ParseErrorKind::InvalidEscape(c), span);
+            return None;
+        }
+        Some(text.chars().map(Element::Char).collect())
+    }
+
+    fn number(&mut self, text: &str, span: Span) -> Option<Number> {
+        let normalized = text.replace('¯', "-");
+        let n = if normalized.contains(['.', 'e', 'E']) {
+            // Too large an exponent parses as infinity, which no literal means
+            normalized
+                .parse::<f64>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .map(Number::Float)
+        } else {
+            normalized.parse::<BigInt>().ok().map(Number::from)
+        };
+        if n.is_none() {
+            self.error(ParseErrorKind::InvalidNumber(text.to_string()), span);
+        }
+        n
+    }
+
+    fn error(&mut self, kind: ParseErrorKind, span: Span) {
+        self.errors.push(ParseError { kind, span });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(input: &str) -> Vec<Op> {
+        parse(input)
+            .unwrap()
+            .into_iter()
+            .map(|instruction| instruction.op)
+            .collect()
+    }
+
+    fn errors(input: &str) -> Vec<ParseErrorKind> {
+        parse(input)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.kind)
+            .collect()
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(
+            ops("2 [3] [1 2] 0.5"),
+            [
+                Op::Number(Number::Int(2)),
+                Op::Number(Number::Int(3)),
+                Op::Array([1, 2].map(Number::Int).into_iter().collect()),
+                Op::Number(Number::Float(0.5)),
+            ]
+        );
+        assert_eq!(
+            ops(r#""hi""#),
+            [Op::Array("hi".chars().map(Element::Char).collect())]
+        );
+    }
+
+    #[test]
+    fn integers_too_big_for_an_i32_are_big() {
+        let big = |n: &str| Number::Big(n.parse().unwrap());
+        assert_eq!(
+            ops("2147483647 99999999999 ¯2147483648 [1 ¯2147483649]"),
+            [
+                Op::Number(Number::Int(i32::MAX)),
+                Op::Number(big("99999999999")),
+                Op::Number(Number::Int(i32::MIN)),
+                Op::Array(Value::new(vec![
+                    Element::Num(Number::Int(1)),
+                    Element::Num(big("-2147483649")),
+                ])),
+            ]
+        );
+        assert!(matches!(&ops("2147483647")[0], Op::Number(Number::Int(_))));
+    }
+
+    #[test]
+    fn floats_must_be_complete_and_finite() {
+        assert_eq!(ops("1e308"), [Op::Number(Number::Float(1e308))]);
+        for literal in ["1e999", "¯1e999", "1.5E400", "1e", "2E-", "3e¯"] {
+            assert_eq!(
+                errors(literal),
+                [ParseErrorKind::InvalidNumber(literal.into())]
+            );
+        }
+    }
+
+    #[test]
+    fn nested_arrays_are_boxed() {
+        let inner: Value = [1, 2].map(Number::Int).into_iter().collect();
+        assert_eq!(
+            ops("[[1 2] 3]"),
+            [Op::Array(Value::new(vec![
+                Element::Boxed(inner),
+                Element::Num(Number::Int(3)),
+            ]))]
+        );
+    }
+
+    #[test]
+    fn quotations_definitions_and_names() {
+        let program = ops(": sq ⊕ × ; (1 +) →f sq");
+        assert_eq!(program.len(), 4);
+        assert!(
+            matches!(&program[0], Op::Define(name, body) if name == "sq" && body.source() == "⊕ ×")
+        );
+        assert!(matches!(&program[1], Op::Quotation(body) if body.source() == "1 +"));
+        assert_eq!(program[2], Op::Assign("f".into()));
+        assert_eq!(program[3], Op::Name("sq".into()));
+    }
+
+    #[test]
+    fn adjacent_glyph_becomes_a_quotation() {
+        let program = ops("+⌿ + ⌿");
+        assert!(matches!(&program[0], Op::Quotation(body) if body.source() == "+"));
+        assert_eq!(program[1..], [Op::Reduce, Op::Add, Op::Reduce]);
+    }
+
+    #[test]
+    fn reports_every_error() {
+        assert_eq!(
+            errors("] ) ; $ [1 +]"),
+            [
+                ParseErrorKind::UnmatchedCloseBracket,
+                ParseErrorKind::UnmatchedCloseParen,
+                ParseErrorKind::UnmatchedSemicolon,
+                ParseErrorKind::UnknownGlyph('$'),
+                ParseErrorKind::GlyphInArray('+'),
+            ]
+        );
+        assert_eq!(errors("[1 x]"), [ParseErrorKind::NameInArray("x".into())]);
+        assert_eq!(errors("(1"), [ParseErrorKind::UnterminatedQuotation]);
+        assert_eq!(errors("[1"), [ParseErrorKind::UnterminatedArray]);
+        assert_eq!(errors(r#""1"#), [ParseErrorKind::UnterminatedString]);
+        assert_eq!(
+            errors(r#""\q" ["a\z"]"#),
+            [
+                ParseErrorKind::InvalidEscape('q'),
+                ParseErrorKind::InvalidEscape('z'),
+            ]
+        );
+        assert_eq!(errors(": f 1"), [ParseErrorKind::UnterminatedDefinition]);
+        assert_eq!(errors("→ 1"), [ParseErrorKind::ExpectedName('→')]);
+    }
+
+    #[test]
+    fn error_spans_point_at_the_token() {
+        let error = &parse("1 2 $").unwrap_err()[0];
+        assert_eq!((error.span.start, error.span.column), (4, 5));
+        assert_eq!(error.to_string(), "1:5: unknown glyph '$'");
+    }
+}
@@ -0,0 +1,347 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::error::EvalError;
+use crate::number::Number;
+use crate::operations::Quotation;
+
+/// A single item of an array: a number, a character, a boxed array, which
+/// lets arrays nest (`[[1 2] [3]]`), or a quotation. Elements of different
+/// kinds sort in that order.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Element {
+    Num(Number),
+    Char(char),
+    Boxed(Value),
+    Quotation(Quotation),
+}
+
+impl Element {
+    pub fn as_number(&self) -> Result<&Number, EvalError> {
+        match self {
+            Element::Num(n) => Ok(n),
+            Element::Char(_) => Err(EvalError::domain_error(
+                "expected a number, found a character",
+            )),
+            Element::Boxed(_) => Err(EvalError::domain_error("expected a number, found a box")),
+            Element::Quotation(_) => Err(EvalError::domain_error(
+                "expected a number, found a quotation",
+            )),
+        }
+    }
+}
+
+impl From<bool> for Element {
+    fn from(b: bool) -> Self {
+        Element::Num(Number::from(b))
+    }
+}
+
+impl From<Number> for Element {
+    fn from(n: Number) -> Self {
+        Element::Num(n)
+    }
+}
+
+/// The most elements a glyph will build a single array out of, so that a
+/// typo like `[1] [100000 100000] ⍴` is an error rather than an attempt to
+/// allocate more memory than the machine has.
+pub const MAX_ELEMENTS: usize = 1 << 24;
+
+/// A value on the stack: an array of elements with a shape. The data is
+/// stored flat in row-major order, so a `[2 3]` matrix holds 6 elements.
+/// Every value has at least one axis; single numbers have shape `[1]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Value {
+    pub shape: Vec<usize>,
+    pub data: Vec<Element>,
+}
+
+/// Values order by their elements first, like words in a dictionary, so
+/// boxed strings sort alphabetically rather than by length. Values with the
+/// same elements order by shape.
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.data
+            .cmp(&other.data)
+            .then_with(|| self.shape.cmp(&other.shape))
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Value {
+    /// A flat list of elements.
+    pub fn new(data: Vec<Element>) -> Self {
+        Value {
+            shape: vec![data.len()],
+            data,
+        }
+    }
+
+    pub fn scalar(element: impl Into<Element>) -> Self {
+        Value::new(vec![element.into()])
+    }
+
+    pub fn with_shape(shape: Vec<usize>, data: Vec<Element>) -> Self {
+        debug_assert_eq!(shape.iter().product::<usize>(), data.len());
+        Value { shape, data }
+    }
+
+    /// How many elements a value of `shape` holds, which mustn't be more than
+    /// `MAX_ELEMENTS`.
+    pub fn size(shape: &[usize]) -> Result<usize, EvalError> {
+        shape
+            .iter()
+            .try_fold(1usize, |size, &len| size.checked_mul(len))
+            .filter(|&size| size <= MAX_ELEMENTS)
+            .ok_or_else(|| {
+                EvalError::domain_error(format!(
+                    "would make an array of more than {MAX_ELEMENTS} elements"
+                ))
+            })
+    }
+
+    pub fn rank(&self) -> usize {
+        self.shape.len()
+    }
+
+    /// The length of the leading axis, i.e. how many rows a matrix has.
+    pub fn len(&self) -> usize {
+        self.shape[0]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The shape of a single row (major cell). Rows of a list are single
+    /// elements, which have shape `[1]`.
+    pub fn cell_shape(&self) -> Vec<usize> {
+        if self.rank() > 1 {
+            self.shape[1..].to_vec()
+        } else {
+            vec![1]
+        }
+    }
+
+    /// How many elements make up a single row.
+    pub fn cell_len(&self) -> usize {
+        self.shape[1..].iter().product()
+    }
+
+    /// Splits the value into its rows along the leading axis.
+    pub fn cells(&self) -> Vec<&[Element]> {
+        match self.cell_len() {
+            0 => vec![&[]; self.len()],
+            n => self.data.chunks(n).collect(),
+        }
+    }
+
+    /// Builds a value with the same row shape as `self` out of `cells`.
+    pub fn with_cells(&self, cells: &[&[Element]]) -> Value {
+        let mut shape = self.shape.clone();
+        shape[0] = cells.len();
+        Value::with_shape(shape, cells.concat())
+    }
+
+    /// Pulls out a single row as a value of its own.
+    pub fn cell(&self, i: usize) -> Value {
+        let n = self.cell_len();
+        Value::with_shape(self.cell_shape(), self.data[i * n..(i + 1) * n].to_vec())
+    }
+
+    /// The order of row indices that sorts the value, ascending or
+    /// descending. Equal rows keep their original order.
+    pub fn grade(&self, descending: bool) -> Vec<usize> {
+        let cells = self.cells();
+        let mut order: Vec<usize> = (0..cells.len()).collect();
+        if descending {
+            order.sort_by(|&i, &j| cells[j].cmp(cells[i]));
+        } else {
+            order.sort_by(|&i, &j| cells[i].cmp(cells[j]));
+        }
+        order
+    }
+
+    /// Pulls out a row for a quotation to work on. A row that is a single
+    /// box is opened, so quotations see the arrays inside nested arrays.
+    pub fn row(&self, i: usize) -> Value {
+        let cell = self.cell(i);
+        match cell.data.as_slice() {
+            [Element::Boxed(inner)] => inner.clone(),
+            _ => cell,
+        }
+    }
+
+    /// How many boxes deep the value goes; a value holding no boxes has
+    /// depth 0.
+    pub fn depth(&self) -> usize {
+        self.data
+            .iter()
+            .map(|element| match element {
+                Element::Boxed(inner) => inner.depth() + 1,
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Boxes a value, as long as that doesn't nest boxes more than
+    /// `max_depth` deep. Deeper values couldn't even be dropped safely.
+    pub fn boxed(self, max_depth: usize) -> Result<Element, EvalError> {
+        if self.depth() >= max_depth {
+            return Err(EvalError::nesting_limit(max_depth));
+        }
+        Ok(Element::Boxed(self))
+    }
+
+    /// Builds an array whose rows are `rows`, which must all have the same
+    /// shape. Otherwise each row is boxed, giving a nested list, which may
+    /// nest boxes at most `max_depth` deep.
+    pub fn from_rows(rows: Vec<Value>, max_depth: usize) -> Result<Value, EvalError> {
+        let Some(first) = rows.first() else {
+            return Ok(Value::new(Vec::new()));
+        };
+        if rows.iter().any(|row| row.shape != first.shape) {
+            return rows.into_iter().map(|row| row.boxed(max_depth)).collect();
+        }
+        let shape = match first.shape.as_slice() {
+            [1] => vec![rows.len()],
+            shape => [&[rows.len()], shape].concat(),
+        };
+        let data = rows.into_iter().flat_map(|row| row.data).collect();
+        Ok(Value::with_shape(shape, data))
+    }
+
+    /// Whether the value counts as true for a condition: every element must
+    /// be non-zero, and an empty array is false.
+    pub fn truthy(&self) -> Result<bool, EvalError> {
+        if self.data.is_empty() {
+            return Ok(false);
+        }
+        for (i, element) in self.data.iter().enumerate() {
+            if element.as_number().map_err(|e| e.at_index(i))?.is_zero() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The first element as a number, for glyphs that take a single number.
+    pub fn first_number(&self) -> Result<&Number, EvalError> {
+        self.data
+            .first()
+            .ok_or_else(EvalError::empty_operand)?
+            .as_number()
+    }
+
+    /// Rearranges the data into `shape`, repeating it as often as needed.
+    pub fn reshape(&self, shape: Vec<usize>) -> Result<Value, EvalError> {
+        if shape.is_empty() {
+            return Err(EvalError::domain_error("needs at least one axis"));
+        }
+        let size = Value::size(&shape)?;
+        if size > 0 && self.data.is_empty() {
+            return Err(EvalError::empty_operand());
+        }
+        let data = self.data.iter().cycle().take(size).cloned().collect();
+        Ok(Value::with_shape(shape, data))
+    }
+
+    /// Reverses the order of the axes, so rows become columns.
+    pub fn transpose(&self) -> Value {
+        let shape: Vec<usize> = self.shape.iter().rev().copied().collect();
+        let mut strides = vec![1; self.rank()];
+        for axis in (0..self.rank().saturating_sub(1)).rev() {
+            strides[axis] = strides[axis + 1] * self.shape[axis + 1];
+        }
+        strides.reverse();
+
+        let data = (0..self.data.len())
+            .map(|mut flat| {
+                let mut source = 0;
+                for (len, stride) in shape.iter().zip(&strides).rev() {
+                    source += (flat % len) * stride;
+                    flat /= len;
+                }
+                self.data[source].clone()
+            })
+            .collect();
+        Value::with_shape(shape, data)
+    }
+}
+
+impl FromIterator<Number> for Value {
+    fn from_iter<I: IntoIterator<Item = Number>>(iter: I) -> Self {
+        Value::new(iter.into_iter().map(Element::Num).collect())
+    }
+}
+
+impl FromIterator<Element> for Value {
+    fn from_iter<I: IntoIterator<Item = Element>>(iter: I) -> Self {
+        Value::new(iter.into_iter().collect())
+    }
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Element::Num(n) => write!(f, "{n}"),
+            Element::Char(c) => write!(f, "{c:?}"),
+            Element::Boxed(v) => write!(f, "{v}"),
+            Element::Quotation(q) => write!(f, "{q}"),
+        }
+    }
+}
+
+/// Lists print on one line as `[1, 2, 3]`. Higher-rank values print as a grid
+/// with their columns right-aligned, one row per line. Non-empty character
+/// arrays print as strings, e.g. `"hello"`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = !self.data.is_empty() && self.data.iter().all(|e| matches!(e, Element::Char(_)));
+        let items: Vec<String> = self
+            .data
+            .iter()
+            .map(|e| match e {
+                Element::Char(c) if text => c.to_string(),
+                e => e.to_string(),
+            })
+            .collect();
+        let width = if self.rank() > 1 && !text {
+            items.iter().map(|s| s.chars().count()).max().unwrap_or(0)
+        } else {
+            0
+        };
+        write!(f, "{}", grid(&self.shape, &items, width, 0, text))
+    }
+}
+
+fn grid(shape: &[usize], items: &[String], width: usize, depth: usize, text: bool) -> String {
+    if shape.len() == 1 && text {
+        return format!("{:?}", items.concat());
+    }
+    if shape.len() == 1 {
+        let items: Vec<String> = items.iter().map(|s| format!("{s:>width$}")).collect();
+        return format!("[{}]", items.join(", "));
+    }
+
+    let cell_len: usize = shape[1..].iter().product();
+    let rows: Vec<String> = (0..shape[0])
+        .map(|i| {
+            let cell = &items[i * cell_len..(i + 1) * cell_len];
+            grid(&shape[1..], cell, width, depth + 1, text)
+        })
+        .collect();
+    let separator = format!(
+        ",\n{}{}",
+        "\n".repeat(shape.len() - 2),
+        " ".repeat(depth + 1)
+    );
+    format!("[{}]", rows.join(&separator))
+}